@@ -77,6 +77,7 @@
                 "rs"
                 "toml"
                 "json"
+                "pem"
               ])
             );
         };
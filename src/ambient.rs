@@ -0,0 +1,252 @@
+use std::sync::Arc;
+
+use rgb::RGB8;
+use smart_leds_matrix::layout::Layout;
+use smart_leds_matrix::SmartLedMatrix;
+use smart_leds_trait::SmartLedsWrite;
+use time::OffsetDateTime;
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::AutoBrightnessConfig;
+use crate::config::LuxPointConfig;
+use crate::state::SharedState;
+
+/// Latest illuminance received from the ambient light sensor
+#[derive(Debug)]
+pub struct AmbientLight {
+    topic: String,
+    json_pointer: Option<String>,
+    lux: Option<f32>,
+}
+
+impl AmbientLight {
+    pub fn new(config: &AutoBrightnessConfig) -> Self {
+        Self {
+            topic: config.topic.clone(),
+            json_pointer: config.json_pointer.clone(),
+            lux: None,
+        }
+    }
+
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Update the illuminance if `topic` is the topic of the sensor
+    ///
+    /// Returns whether `topic` is the topic of the sensor.
+    pub fn update(&mut self, topic: &str, payload: &[u8]) -> bool {
+        if topic != self.topic {
+            return false;
+        }
+
+        match crate::sensor::parse_value(payload, self.json_pointer.as_deref()) {
+            Ok(lux) => {
+                tracing::debug!(?topic, lux, "Updated ambient light");
+                self.lux = Some(lux);
+            }
+            Err(error) => {
+                tracing::warn!(?topic, ?error, "Failed to parse ambient light, ignoring");
+            }
+        }
+
+        true
+    }
+}
+
+/// Maps the illuminance to a brightness, smoothed and with hysteresis
+#[derive(Debug)]
+pub struct AutoBrightness {
+    config: AutoBrightnessConfig,
+    smoothed_lux: Option<f32>,
+    applied: Option<u8>,
+}
+
+impl AutoBrightness {
+    pub fn new(mut config: AutoBrightnessConfig) -> Result<Self, crate::error::Error> {
+        if config.curve.is_empty() {
+            return Err(crate::error::Error::EmptyBrightnessCurve);
+        }
+
+        config.curve.sort_by(|a, b| a.lux.total_cmp(&b.lux));
+
+        Ok(Self {
+            config,
+            smoothed_lux: None,
+            applied: None,
+        })
+    }
+
+    /// Feed the current illuminance, returns the brightness to apply
+    pub fn next(&mut self, lux: f32) -> u8 {
+        let smoothing = self.config.smoothing.clamp(0.0, 1.0);
+        let smoothed_lux = match self.smoothed_lux {
+            Some(previous) => previous * smoothing + lux * (1.0 - smoothing),
+            None => lux,
+        };
+        self.smoothed_lux = Some(smoothed_lux);
+
+        let target = brightness_for(&self.config.curve, smoothed_lux);
+        let applied = match self.applied {
+            Some(applied) if applied.abs_diff(target) < self.config.hysteresis => applied,
+            _ => target,
+        };
+        self.applied = Some(applied);
+        applied
+    }
+}
+
+/// Brightness on the curve at `lux`, the curve must be sorted and not empty
+fn brightness_for(curve: &[LuxPointConfig], lux: f32) -> u8 {
+    let Some(next_idx) = curve.iter().position(|point| point.lux > lux) else {
+        return curve[curve.len() - 1].brightness;
+    };
+    if next_idx == 0 {
+        return curve[0].brightness;
+    }
+
+    let previous = curve[next_idx - 1];
+    let next = curve[next_idx];
+    let fraction = (lux - previous.lux) / (next.lux - previous.lux);
+    let brightness = f32::from(previous.brightness)
+        + (f32::from(next.brightness) - f32::from(previous.brightness)) * fraction;
+    brightness.round() as u8
+}
+
+pub struct AutoBrightnessTask<T, L, const SIZE: usize>
+where
+    T: SmartLedsWrite,
+    L: Layout,
+    <T as SmartLedsWrite>::Color: From<RGB8>,
+{
+    auto_brightness: AutoBrightness,
+    ambient_light: Arc<Mutex<AmbientLight>>,
+    cancellation_token: CancellationToken,
+    matrix: Arc<Mutex<SmartLedMatrix<T, L, SIZE>>>,
+    manual_brightness_set_at: Arc<Mutex<Option<OffsetDateTime>>>,
+    clock_state: SharedState,
+}
+
+impl<T, L, const SIZE: usize> AutoBrightnessTask<T, L, SIZE>
+where
+    T: SmartLedsWrite,
+    L: Layout,
+    <T as SmartLedsWrite>::Color: From<RGB8>,
+{
+    pub fn new(
+        auto_brightness: AutoBrightness,
+        ambient_light: Arc<Mutex<AmbientLight>>,
+        cancellation_token: CancellationToken,
+        matrix: Arc<Mutex<SmartLedMatrix<T, L, SIZE>>>,
+        manual_brightness_set_at: Arc<Mutex<Option<OffsetDateTime>>>,
+        clock_state: SharedState,
+    ) -> Self {
+        Self {
+            auto_brightness,
+            ambient_light,
+            cancellation_token,
+            matrix,
+            manual_brightness_set_at,
+            clock_state,
+        }
+    }
+
+    pub async fn run(mut self) -> Result<(), crate::error::Error> {
+        let mut apply_interval = tokio::time::interval(self.auto_brightness.config.interval);
+
+        loop {
+            let Some(_tick) = self
+                .cancellation_token
+                .run_until_cancelled(apply_interval.tick())
+                .await
+            else {
+                tracing::info!("Ending automatic brightness");
+                break;
+            };
+
+            {
+                let mut manual_brightness_set_at = self.manual_brightness_set_at.lock().await;
+                if let Some(set_at) = *manual_brightness_set_at {
+                    let now =
+                        OffsetDateTime::now_local().map_err(crate::error::Error::TimeOffset)?;
+                    if now < set_at + self.auto_brightness.config.override_timeout {
+                        tracing::trace!(?set_at, "Brightness set manually, not applying");
+                        continue;
+                    }
+
+                    tracing::info!("Manual brightness expired, returning to automatic brightness");
+                    *manual_brightness_set_at = None;
+                }
+            }
+
+            let Some(lux) = self.ambient_light.lock().await.lux else {
+                tracing::trace!("No ambient light received yet");
+                continue;
+            };
+
+            // Always applied, so that brightness changes from elsewhere (e.g. "TurnOn") are
+            // corrected as well
+            let brightness =
+                crate::brightness::clamp_set_brightness(self.auto_brightness.next(lux));
+            tracing::trace!(?lux, ?brightness, "Applying automatic brightness");
+            self.matrix.lock().await.set_brightness(brightness);
+            self.clock_state
+                .update(|state| state.brightness = brightness);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AutoBrightness;
+    use crate::config::AutoBrightnessConfig;
+    use crate::config::LuxPointConfig;
+
+    fn auto_brightness(smoothing: f32, hysteresis: u8) -> AutoBrightness {
+        AutoBrightness::new(AutoBrightnessConfig {
+            topic: String::from("sensors/lux"),
+            json_pointer: None,
+            interval: std::time::Duration::from_secs(1),
+            smoothing,
+            hysteresis,
+            override_timeout: std::time::Duration::from_secs(60),
+            curve: vec![
+                LuxPointConfig {
+                    lux: 1000.0,
+                    brightness: 100,
+                },
+                LuxPointConfig {
+                    lux: 0.0,
+                    brightness: 10,
+                },
+            ],
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_curve_is_interpolated_and_clamped() {
+        assert_eq!(auto_brightness(0.0, 0).next(500.0), 55);
+        assert_eq!(auto_brightness(0.0, 0).next(5000.0), 100);
+        assert_eq!(auto_brightness(0.0, 0).next(-1.0), 10);
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        let mut auto_brightness = auto_brightness(0.0, 5);
+        assert_eq!(auto_brightness.next(500.0), 55);
+        assert_eq!(auto_brightness.next(540.0), 55);
+        assert_eq!(auto_brightness.next(560.0), 60);
+    }
+
+    #[test]
+    fn test_smoothing() {
+        let mut auto_brightness = auto_brightness(0.5, 0);
+        assert_eq!(auto_brightness.next(1000.0), 100);
+        assert_eq!(auto_brightness.next(0.0), 55);
+        assert_eq!(auto_brightness.next(0.0), 33);
+    }
+}
@@ -0,0 +1,203 @@
+use time::Date;
+use time::OffsetDateTime;
+use time::UtcOffset;
+
+use crate::config::LocationConfig;
+use crate::config::ScheduleTime;
+
+/// Zenith of the sun at sunrise and sunset, including refraction
+const OFFICIAL_ZENITH_DEGREES: f64 = 90.833;
+
+/// Mean length of a lunar cycle in days
+const SYNODIC_MONTH_DAYS: f64 = 29.530_588_853;
+
+/// A known new moon, 2000-01-06 18:14 UTC
+const REFERENCE_NEW_MOON_UNIX: i64 = 947_182_440;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SunTimes {
+    /// `None` if the sun does not rise on that day (polar night)
+    pub sunrise: Option<OffsetDateTime>,
+
+    /// `None` if the sun does not set on that day (midnight sun)
+    pub sunset: Option<OffsetDateTime>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum SunEvent {
+    Rise,
+    Set,
+}
+
+/// Sunrise and sunset in UTC on `date` at `location`
+pub fn sun_times(date: Date, location: &LocationConfig) -> SunTimes {
+    SunTimes {
+        sunrise: sun_event(date, location, SunEvent::Rise),
+        sunset: sun_event(date, location, SunEvent::Set),
+    }
+}
+
+/// Sunrise equation from the Almanac for Computers (1990), accurate to about a minute
+fn sun_event(date: Date, location: &LocationConfig, event: SunEvent) -> Option<OffsetDateTime> {
+    let longitude_hours = location.longitude / 15.0;
+    let approximate_hour = match event {
+        SunEvent::Rise => 6.0,
+        SunEvent::Set => 18.0,
+    };
+    let t = f64::from(date.ordinal()) + (approximate_hour - longitude_hours) / 24.0;
+
+    let mean_anomaly = 0.9856 * t - 3.289;
+    let true_longitude = (mean_anomaly
+        + 1.916 * sin_deg(mean_anomaly)
+        + 0.020 * sin_deg(2.0 * mean_anomaly)
+        + 282.634)
+        .rem_euclid(360.0);
+
+    // Right ascension has to be in the same quadrant as the true longitude
+    let right_ascension = (0.91764 * tan_deg(true_longitude))
+        .atan()
+        .to_degrees()
+        .rem_euclid(360.0);
+    let right_ascension = right_ascension + (true_longitude / 90.0).floor() * 90.0
+        - (right_ascension / 90.0).floor() * 90.0;
+    let right_ascension_hours = right_ascension / 15.0;
+
+    let sin_declination = 0.39782 * sin_deg(true_longitude);
+    let cos_declination = sin_declination.asin().cos();
+
+    let cos_hour_angle = (cos_deg(OFFICIAL_ZENITH_DEGREES)
+        - sin_declination * sin_deg(location.latitude))
+        / (cos_declination * cos_deg(location.latitude));
+    if !(-1.0..=1.0).contains(&cos_hour_angle) {
+        return None;
+    }
+
+    let hour_angle = cos_hour_angle.acos().to_degrees();
+    let hour_angle_hours = match event {
+        SunEvent::Rise => 360.0 - hour_angle,
+        SunEvent::Set => hour_angle,
+    } / 15.0;
+
+    let local_mean_time = hour_angle_hours + right_ascension_hours - 0.06571 * t - 6.622;
+
+    // Far from Greenwich, the event may be on the previous or next day in UTC
+    let expected_utc_hours = approximate_hour - longitude_hours;
+    let utc_hours = expected_utc_hours
+        + (local_mean_time - longitude_hours - expected_utc_hours + 12.0).rem_euclid(24.0)
+        - 12.0;
+
+    let midnight = date.midnight().assume_utc();
+    Some(midnight + time::Duration::seconds_f64(utc_hours * 3600.0))
+}
+
+fn sin_deg(degrees: f64) -> f64 {
+    degrees.to_radians().sin()
+}
+
+fn cos_deg(degrees: f64) -> f64 {
+    degrees.to_radians().cos()
+}
+
+fn tan_deg(degrees: f64) -> f64 {
+    degrees.to_radians().tan()
+}
+
+/// The point in time `at` refers to on `date`, with `offset` as local offset
+///
+/// Returns `None` for sun-relative times if there is no location, or if the sun does not rise or
+/// set on that day.
+pub fn resolve_schedule_time(
+    at: ScheduleTime,
+    date: Date,
+    offset: UtcOffset,
+    location: Option<&LocationConfig>,
+) -> Option<OffsetDateTime> {
+    match at {
+        ScheduleTime::Clock { at } => Some(date.with_time(at).assume_offset(offset)),
+        ScheduleTime::Sun {
+            sun,
+            offset_minutes,
+        } => {
+            let sun_times = sun_times(date, location?);
+            let sun_event = match sun {
+                crate::config::SunEvent::Sunrise => sun_times.sunrise,
+                crate::config::SunEvent::Sunset => sun_times.sunset,
+            };
+
+            sun_event
+                .map(|at| at.to_offset(offset) + time::Duration::minutes(offset_minutes.into()))
+        }
+    }
+}
+
+/// Position in the lunar cycle at `at`, `0.0` is new moon and `0.5` is full moon
+pub fn moon_phase(at: OffsetDateTime) -> f64 {
+    let days = (at.unix_timestamp() - REFERENCE_NEW_MOON_UNIX) as f64 / 86400.0;
+    (days / SYNODIC_MONTH_DAYS).rem_euclid(1.0)
+}
+
+/// Illuminated fraction of the moon at `phase`
+pub fn moon_illumination(phase: f64) -> f64 {
+    (1.0 - (phase * std::f64::consts::TAU).cos()) / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::date;
+    use time::macros::datetime;
+
+    use super::moon_phase;
+    use super::sun_times;
+    use crate::config::LocationConfig;
+
+    const BERLIN: LocationConfig = LocationConfig {
+        latitude: 52.52,
+        longitude: 13.405,
+    };
+
+    #[test]
+    fn test_sun_times_berlin_midsummer() {
+        let sun_times = sun_times(date!(2025 - 06 - 21), &BERLIN);
+
+        let sunrise = sun_times.sunrise.unwrap();
+        let expected_sunrise = datetime!(2025-06-21 02:43 UTC);
+        assert!((sunrise - expected_sunrise).abs() < time::Duration::minutes(3));
+
+        let sunset = sun_times.sunset.unwrap();
+        let expected_sunset = datetime!(2025-06-21 19:33 UTC);
+        assert!((sunset - expected_sunset).abs() < time::Duration::minutes(3));
+    }
+
+    #[test]
+    fn test_sun_times_west_of_greenwich() {
+        let san_francisco = LocationConfig {
+            latitude: 37.77,
+            longitude: -122.42,
+        };
+        let sun_times = sun_times(date!(2025 - 06 - 21), &san_francisco);
+
+        let sunset = sun_times.sunset.unwrap();
+        let expected_sunset = datetime!(2025-06-22 03:35 UTC);
+        assert!((sunset - expected_sunset).abs() < time::Duration::minutes(3));
+    }
+
+    #[test]
+    fn test_polar_night() {
+        let tromso = LocationConfig {
+            latitude: 69.65,
+            longitude: 18.96,
+        };
+        let sun_times = sun_times(date!(2025 - 12 - 21), &tromso);
+        assert!(sun_times.sunrise.is_none());
+        assert!(sun_times.sunset.is_none());
+    }
+
+    #[test]
+    fn test_moon_phase() {
+        let full_moon = moon_phase(datetime!(2025-10-07 03:47 UTC));
+        assert!((full_moon - 0.5).abs() < 0.03);
+
+        let new_moon = moon_phase(datetime!(2025-10-21 12:25 UTC));
+        assert!(!(0.03..=0.97).contains(&new_moon));
+    }
+}
@@ -0,0 +1,268 @@
+use std::sync::Arc;
+
+use rgb::RGB8;
+use smart_leds_matrix::layout::Layout;
+use smart_leds_matrix::SmartLedMatrix;
+use smart_leds_trait::SmartLedsWrite;
+use time::OffsetDateTime;
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::BrightnessScheduleConfig;
+use crate::config::LocationConfig;
+use crate::config::ScheduleTime;
+use crate::state::SharedState;
+
+/// Clamp a brightness that is not set by the schedule, so that the display does not go dark
+pub fn clamp_set_brightness(brightness: u8) -> u8 {
+    brightness.clamp(5, 100)
+}
+
+#[derive(Debug, Clone)]
+pub struct BrightnessSchedule {
+    config: BrightnessScheduleConfig,
+    location: Option<LocationConfig>,
+}
+
+impl BrightnessSchedule {
+    pub fn new(
+        config: BrightnessScheduleConfig,
+        location: Option<LocationConfig>,
+    ) -> Result<Self, crate::error::Error> {
+        let sun_relative = config
+            .points
+            .iter()
+            .any(|point| std::matches!(point.at, ScheduleTime::Sun { .. }));
+
+        if sun_relative && location.is_none() {
+            return Err(crate::error::Error::MissingLocation);
+        }
+
+        Ok(Self { config, location })
+    }
+
+    /// The points of the schedule on the day of `at`, sorted by time
+    fn points_on(&self, at: OffsetDateTime) -> Vec<(OffsetDateTime, u8)> {
+        let mut points = self
+            .config
+            .points
+            .iter()
+            .filter_map(|point| {
+                crate::astro::resolve_schedule_time(
+                    point.at,
+                    at.date(),
+                    at.offset(),
+                    self.location.as_ref(),
+                )
+                .map(|point_at| (point_at, point.brightness))
+            })
+            .collect::<Vec<_>>();
+
+        points.sort_by_key(|(point_at, _)| *point_at);
+        points
+    }
+
+    /// The scheduled brightness at `now`, or `None` if there are no points
+    pub fn brightness_at(&self, now: OffsetDateTime) -> Option<u8> {
+        let yesterday = now.date().previous_day().map(|date| now.replace_date(date));
+        let tomorrow = now.date().next_day().map(|date| now.replace_date(date));
+
+        // The last point of yesterday and the first point of tomorrow are needed around midnight
+        let points = yesterday
+            .map(|yesterday| self.points_on(yesterday))
+            .unwrap_or_default()
+            .into_iter()
+            .chain(self.points_on(now))
+            .chain(
+                tomorrow
+                    .map(|tomorrow| self.points_on(tomorrow))
+                    .unwrap_or_default(),
+            )
+            .collect::<Vec<_>>();
+
+        let (previous_at, previous) = points.iter().rev().find(|(at, _)| *at <= now)?;
+        let Some((next_at, next)) = points.iter().find(|(at, _)| *at > now) else {
+            return Some(*previous);
+        };
+
+        if !self.config.interpolate {
+            return Some(*previous);
+        }
+
+        let fraction = (now - *previous_at) / (*next_at - *previous_at);
+        let brightness =
+            f64::from(*previous) + (f64::from(*next) - f64::from(*previous)) * fraction;
+        Some(brightness.round() as u8)
+    }
+
+    /// Until when a brightness set manually at `set_at` overrides the schedule
+    ///
+    /// Returns `None` if the override never ends, because there are no points and no timeout.
+    pub fn override_end(&self, set_at: OffsetDateTime) -> Option<OffsetDateTime> {
+        let tomorrow = set_at
+            .date()
+            .next_day()
+            .map(|date| set_at.replace_date(date));
+        let next_point = self
+            .points_on(set_at)
+            .into_iter()
+            .chain(
+                tomorrow
+                    .map(|tomorrow| self.points_on(tomorrow))
+                    .unwrap_or_default(),
+            )
+            .map(|(at, _)| at)
+            .find(|at| *at > set_at);
+
+        let timeout = self.config.override_timeout.map(|timeout| set_at + timeout);
+
+        match (next_point, timeout) {
+            (Some(next_point), Some(timeout)) => Some(next_point.min(timeout)),
+            (next_point, timeout) => next_point.or(timeout),
+        }
+    }
+}
+
+pub struct BrightnessTask<T, L, const SIZE: usize>
+where
+    T: SmartLedsWrite,
+    L: Layout,
+    <T as SmartLedsWrite>::Color: From<RGB8>,
+{
+    schedule: BrightnessSchedule,
+    cancellation_token: CancellationToken,
+    matrix: Arc<Mutex<SmartLedMatrix<T, L, SIZE>>>,
+    manual_brightness_set_at: Arc<Mutex<Option<OffsetDateTime>>>,
+    clock_state: SharedState,
+}
+
+impl<T, L, const SIZE: usize> BrightnessTask<T, L, SIZE>
+where
+    T: SmartLedsWrite,
+    L: Layout,
+    <T as SmartLedsWrite>::Color: From<RGB8>,
+{
+    pub fn new(
+        schedule: BrightnessSchedule,
+        cancellation_token: CancellationToken,
+        matrix: Arc<Mutex<SmartLedMatrix<T, L, SIZE>>>,
+        manual_brightness_set_at: Arc<Mutex<Option<OffsetDateTime>>>,
+        clock_state: SharedState,
+    ) -> Self {
+        Self {
+            schedule,
+            cancellation_token,
+            matrix,
+            manual_brightness_set_at,
+            clock_state,
+        }
+    }
+
+    pub async fn run(self) -> Result<(), crate::error::Error> {
+        let mut apply_interval = tokio::time::interval(self.schedule.config.interval);
+
+        loop {
+            let Some(_tick) = self
+                .cancellation_token
+                .run_until_cancelled(apply_interval.tick())
+                .await
+            else {
+                tracing::info!("Ending brightness schedule");
+                break;
+            };
+
+            let now = OffsetDateTime::now_local().map_err(crate::error::Error::TimeOffset)?;
+
+            {
+                let mut manual_brightness_set_at = self.manual_brightness_set_at.lock().await;
+                if let Some(set_at) = *manual_brightness_set_at {
+                    if self
+                        .schedule
+                        .override_end(set_at)
+                        .is_none_or(|end| now < end)
+                    {
+                        tracing::trace!(?set_at, "Brightness set manually, not applying schedule");
+                        continue;
+                    }
+
+                    tracing::info!("Manual brightness expired, returning to schedule");
+                    *manual_brightness_set_at = None;
+                }
+            }
+
+            let Some(brightness) = self.schedule.brightness_at(now) else {
+                continue;
+            };
+
+            // Always applied, so that brightness changes from elsewhere (e.g. "TurnOn") are
+            // corrected as well
+            let brightness = brightness.clamp(0, 100);
+            tracing::trace!(?brightness, "Applying scheduled brightness");
+            self.matrix.lock().await.set_brightness(brightness);
+            self.clock_state
+                .update(|state| state.brightness = brightness);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::datetime;
+    use time::macros::time;
+
+    use super::BrightnessSchedule;
+    use crate::config::BrightnessPointConfig;
+    use crate::config::BrightnessScheduleConfig;
+    use crate::config::ScheduleTime;
+
+    fn schedule(interpolate: bool) -> BrightnessSchedule {
+        let point = |at, brightness| BrightnessPointConfig {
+            at: ScheduleTime::Clock { at },
+            brightness,
+        };
+
+        BrightnessSchedule::new(
+            BrightnessScheduleConfig {
+                interpolate,
+                interval: std::time::Duration::from_secs(60),
+                override_timeout: Some(std::time::Duration::from_secs(60 * 60)),
+                points: vec![point(time!(07:00), 60), point(time!(22:00), 10)],
+            },
+            None,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_interpolates_between_points() {
+        let brightness = schedule(true).brightness_at(datetime!(2025-10-19 14:30 UTC));
+        assert_eq!(brightness, Some(35));
+    }
+
+    #[test]
+    fn test_interpolates_across_midnight() {
+        let brightness = schedule(true).brightness_at(datetime!(2025-10-19 04:00 UTC));
+        assert_eq!(brightness, Some(43));
+    }
+
+    #[test]
+    fn test_steps_without_interpolation() {
+        let brightness = schedule(false).brightness_at(datetime!(2025-10-19 21:59 UTC));
+        assert_eq!(brightness, Some(60));
+    }
+
+    #[test]
+    fn test_override_ends_at_next_point_or_timeout() {
+        let schedule = schedule(true);
+        assert_eq!(
+            schedule.override_end(datetime!(2025-10-19 21:30 UTC)),
+            Some(datetime!(2025-10-19 22:00 UTC))
+        );
+        assert_eq!(
+            schedule.override_end(datetime!(2025-10-19 12:00 UTC)),
+            Some(datetime!(2025-10-19 13:00 UTC))
+        );
+    }
+}
@@ -0,0 +1,646 @@
+use std::sync::Arc;
+
+use time::Date;
+use time::Month;
+use time::OffsetDateTime;
+use time::PrimitiveDateTime;
+use time::Time;
+use time::Weekday;
+use time_tz::OffsetDateTimeExt;
+use time_tz::PrimitiveDateTimeExt;
+use time_tz::Tz;
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::CalendarConfig;
+use crate::error::CalendarError;
+
+/// The next appointment from the configured calendars
+#[derive(Debug, Clone, PartialEq)]
+pub struct Appointment {
+    /// Local start time
+    pub start: PrimitiveDateTime,
+    pub all_day: bool,
+    pub summary: String,
+}
+
+impl Appointment {
+    /// Short description of when the appointment starts, relative to `now`
+    ///
+    /// Only the time for appointments today, the weekday and time or date for later ones.
+    pub fn when(&self, now: PrimitiveDateTime) -> String {
+        let weekday = weekday_abbreviation(self.start.weekday());
+        let today = self.start.date() == now.date();
+
+        match (self.all_day, today) {
+            (true, true) => String::from("Today"),
+            (true, false) => format!(
+                "{weekday} {:02}.{:02}",
+                self.start.day(),
+                u8::from(self.start.month())
+            ),
+            (false, true) => format!("{:02}:{:02}", self.start.hour(), self.start.minute()),
+            (false, false) => format!(
+                "{weekday} {:02}:{:02}",
+                self.start.hour(),
+                self.start.minute()
+            ),
+        }
+    }
+}
+
+/// Two letter abbreviation of `weekday`, short enough for the small font
+pub fn weekday_abbreviation(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Monday => "Mo",
+        Weekday::Tuesday => "Tu",
+        Weekday::Wednesday => "We",
+        Weekday::Thursday => "Th",
+        Weekday::Friday => "Fr",
+        Weekday::Saturday => "Sa",
+        Weekday::Sunday => "Su",
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// The subset of `RRULE` that is supported: `FREQ`, `INTERVAL`, `COUNT`, `UNTIL`, and `BYDAY`
+/// for weekly rules
+#[derive(Debug, Clone)]
+struct RecurrenceRule {
+    frequency: Frequency,
+    interval: u32,
+    count: Option<usize>,
+    until: Option<PrimitiveDateTime>,
+    by_day: Vec<Weekday>,
+}
+
+#[derive(Debug, Clone)]
+struct CalendarEvent {
+    summary: String,
+    start: PrimitiveDateTime,
+    all_day: bool,
+    rule: Option<RecurrenceRule>,
+    exdates: Vec<PrimitiveDateTime>,
+}
+
+pub struct CalendarTask {
+    config: CalendarConfig,
+    cancellation_token: CancellationToken,
+    next_appointment: Arc<Mutex<Option<Appointment>>>,
+}
+
+impl CalendarTask {
+    pub fn new(
+        config: CalendarConfig,
+        cancellation_token: CancellationToken,
+        next_appointment: Arc<Mutex<Option<Appointment>>>,
+    ) -> Self {
+        Self {
+            config,
+            cancellation_token,
+            next_appointment,
+        }
+    }
+
+    pub async fn run(self) -> Result<(), crate::error::Error> {
+        let mut refresh_interval = tokio::time::interval(self.config.refresh_every);
+        let local_timezone =
+            time_tz::system::get_timezone().map_err(crate::error::Error::SystemTimezone)?;
+
+        loop {
+            let Some(_tick) = self
+                .cancellation_token
+                .run_until_cancelled(refresh_interval.tick())
+                .await
+            else {
+                tracing::info!("Ending calendar refresh");
+                break;
+            };
+
+            let mut events = Vec::new();
+            for path in self.config.files.iter() {
+                match load_file(path, local_timezone).await {
+                    Ok(file_events) => events.extend(file_events),
+                    Err(error) => tracing::error!(?error, "Failed to load calendar"),
+                }
+            }
+
+            let now = time::OffsetDateTime::now_local().map_err(crate::error::Error::TimeOffset)?;
+            let now = PrimitiveDateTime::new(now.date(), now.time());
+            let next = next_appointment(&events, now, now + self.config.lookahead);
+            tracing::debug!(?next, "Refreshed next appointment");
+
+            *self.next_appointment.lock().await = next;
+        }
+
+        Ok(())
+    }
+}
+
+async fn load_file(
+    path: &camino::Utf8Path,
+    local_timezone: &Tz,
+) -> Result<Vec<CalendarEvent>, CalendarError> {
+    let content =
+        tokio::fs::read_to_string(path)
+            .await
+            .map_err(|source| CalendarError::ReadingFile {
+                path: path.to_path_buf(),
+                source,
+            })?;
+
+    parse_events(&content, local_timezone)
+}
+
+/// The appointment that starts first in `[after, before)`, or an all-day appointment today
+fn next_appointment(
+    events: &[CalendarEvent],
+    after: PrimitiveDateTime,
+    before: PrimitiveDateTime,
+) -> Option<Appointment> {
+    events
+        .iter()
+        .filter_map(|event| {
+            event
+                .next_occurrence(after, before)
+                .map(|start| Appointment {
+                    start,
+                    all_day: event.all_day,
+                    summary: event.summary.clone(),
+                })
+        })
+        .min_by_key(|appointment| appointment.start)
+}
+
+impl CalendarEvent {
+    /// The first occurrence in `[after, before)`
+    ///
+    /// All-day events are compared by date, so they are found for the whole day.
+    fn next_occurrence(
+        &self,
+        after: PrimitiveDateTime,
+        before: PrimitiveDateTime,
+    ) -> Option<PrimitiveDateTime> {
+        let after = if self.all_day {
+            after.replace_time(Time::MIDNIGHT)
+        } else {
+            after
+        };
+
+        let Some(rule) = self.rule.as_ref() else {
+            return (self.start >= after && self.start < before).then_some(self.start);
+        };
+
+        // COUNT includes excluded occurrences, so they are only filtered afterwards
+        rule.occurrences(self.start)
+            .take_while(|occurrence| *occurrence < before)
+            .filter(|occurrence| !self.exdates.contains(occurrence))
+            .find(|occurrence| *occurrence >= after)
+    }
+}
+
+impl RecurrenceRule {
+    fn occurrences(
+        &self,
+        start: PrimitiveDateTime,
+    ) -> impl Iterator<Item = PrimitiveDateTime> + '_ {
+        let interval = self.interval.max(1);
+
+        (0u32..)
+            .map_while(move |period| {
+                period
+                    .checked_mul(interval)
+                    .and_then(|offset| self.period_occurrences(start, offset))
+            })
+            .flatten()
+            .filter(move |occurrence| *occurrence >= start)
+            .take_while(|occurrence| self.until.is_none_or(|until| *occurrence <= until))
+            .take(self.count.unwrap_or(usize::MAX))
+    }
+
+    /// All occurrences in the period `offset` days/weeks/months/years after `start`
+    ///
+    /// Returns `None` if the period is out of the supported date range.
+    fn period_occurrences(
+        &self,
+        start: PrimitiveDateTime,
+        offset: u32,
+    ) -> Option<Vec<PrimitiveDateTime>> {
+        let date = start.date();
+        let offset = i64::from(offset);
+
+        let dates = match self.frequency {
+            Frequency::Daily => vec![date.checked_add(time::Duration::days(offset))?],
+
+            Frequency::Weekly if self.by_day.is_empty() => {
+                vec![date.checked_add(time::Duration::weeks(offset))?]
+            }
+
+            Frequency::Weekly => {
+                let week_start = date
+                    .checked_sub(time::Duration::days(
+                        date.weekday().number_days_from_monday().into(),
+                    ))?
+                    .checked_add(time::Duration::weeks(offset))?;
+
+                let mut by_day = self.by_day.clone();
+                by_day.sort_by_key(|weekday| weekday.number_days_from_monday());
+                by_day
+                    .into_iter()
+                    .map(|weekday| {
+                        week_start.checked_add(time::Duration::days(
+                            weekday.number_days_from_monday().into(),
+                        ))
+                    })
+                    .collect::<Option<Vec<_>>>()?
+            }
+
+            Frequency::Monthly => {
+                let months = i64::from(u8::from(date.month())) - 1 + offset;
+                let year = i32::try_from(i64::from(date.year()) + months / 12).ok()?;
+                let month = Month::try_from((months % 12 + 1) as u8).ok()?;
+
+                // Months without that day are skipped
+                Date::from_calendar_date(year, month, date.day())
+                    .ok()
+                    .into_iter()
+                    .collect()
+            }
+
+            Frequency::Yearly => {
+                let year = i32::try_from(i64::from(date.year()) + offset).ok()?;
+                Date::from_calendar_date(year, date.month(), date.day())
+                    .ok()
+                    .into_iter()
+                    .collect()
+            }
+        };
+
+        Some(
+            dates
+                .into_iter()
+                .map(|date| PrimitiveDateTime::new(date, start.time()))
+                .collect(),
+        )
+    }
+}
+
+/// Parse the events of `content`, with times converted to `local_timezone`
+fn parse_events(content: &str, local_timezone: &Tz) -> Result<Vec<CalendarEvent>, CalendarError> {
+    let mut events = Vec::new();
+    let mut overrides = Vec::new();
+
+    for calendar in ical::IcalParser::new(content.as_bytes()) {
+        let calendar = calendar.map_err(CalendarError::Parsing)?;
+
+        for event in calendar.events {
+            match parse_event(&event, local_timezone) {
+                Ok((uid, None, event)) => events.push((uid, event)),
+                Ok((uid, Some(recurrence_id), event)) => {
+                    overrides.push((uid, recurrence_id));
+                    events.push((None, event));
+                }
+                Err(error) => tracing::warn!(?error, "Ignoring invalid calendar event"),
+            }
+        }
+    }
+
+    // Modified occurrences of recurring events are separate events that replace the original
+    // occurrence
+    for (uid, recurrence_id) in overrides {
+        let Some(uid) = uid else { continue };
+
+        for (_, event) in events
+            .iter_mut()
+            .filter(|(event_uid, _)| event_uid.as_ref() == Some(&uid))
+        {
+            event.exdates.push(recurrence_id);
+        }
+    }
+
+    Ok(events.into_iter().map(|(_, event)| event).collect())
+}
+
+type ParsedEvent = (Option<String>, Option<PrimitiveDateTime>, CalendarEvent);
+
+fn parse_event(
+    event: &ical::parser::ical::component::IcalEvent,
+    local_timezone: &Tz,
+) -> Result<ParsedEvent, CalendarError> {
+    let mut uid = None;
+    let mut recurrence_id = None;
+    let mut summary = None;
+    let mut start = None;
+    let mut rule = None;
+    let mut exdates = Vec::new();
+
+    for property in event.properties.iter() {
+        let Some(value) = property.value.as_deref() else {
+            continue;
+        };
+        let date_time = |value| parse_date_time(value, tzid(property), local_timezone);
+
+        match property.name.as_str() {
+            "UID" => uid = Some(value.to_string()),
+            "SUMMARY" => summary = Some(unescape(value)),
+            "DTSTART" => start = Some(date_time(value)?),
+            "RECURRENCE-ID" => recurrence_id = Some(date_time(value)?.0),
+            "RRULE" => rule = Some(parse_recurrence_rule(value, local_timezone)?),
+            "EXDATE" => {
+                for value in value.split(',') {
+                    exdates.push(date_time(value)?.0);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let (start, all_day) = start.ok_or(CalendarError::MissingProperty("DTSTART"))?;
+
+    Ok((
+        uid,
+        recurrence_id,
+        CalendarEvent {
+            summary: summary.unwrap_or_default(),
+            start,
+            all_day,
+            rule,
+            exdates,
+        },
+    ))
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("\\n", " ")
+        .replace("\\N", " ")
+        .replace("\\,", ",")
+        .replace("\\;", ";")
+        .replace("\\\\", "\\")
+}
+
+/// The `TZID` parameter of `property`
+fn tzid(property: &ical::property::Property) -> Option<&str> {
+    property
+        .params
+        .as_ref()?
+        .iter()
+        .find(|(name, _)| name == "TZID")
+        .and_then(|(_, values)| values.first())
+        .map(String::as_str)
+}
+
+/// Parse a `DATE` or `DATE-TIME` value into `local_timezone`
+///
+/// Returns whether the value was a date only. Times in UTC and times with a `TZID` parameter are
+/// converted, times without either are floating and taken as they are. Unknown `TZID`s are
+/// treated as floating.
+fn parse_date_time(
+    value: &str,
+    tzid: Option<&str>,
+    local_timezone: &Tz,
+) -> Result<(PrimitiveDateTime, bool), CalendarError> {
+    let invalid = || CalendarError::InvalidDateTime(value.to_string());
+    let number = |range: std::ops::Range<usize>| -> Result<u32, CalendarError> {
+        value
+            .get(range)
+            .and_then(|digits| digits.parse().ok())
+            .ok_or_else(invalid)
+    };
+
+    let date = Date::from_calendar_date(
+        number(0..4)? as i32,
+        Month::try_from(number(4..6)? as u8).map_err(|_| invalid())?,
+        number(6..8)? as u8,
+    )
+    .map_err(|_| invalid())?;
+
+    if value.len() == 8 {
+        return Ok((PrimitiveDateTime::new(date, Time::MIDNIGHT), true));
+    }
+
+    if value.get(8..9) != Some("T") {
+        return Err(invalid());
+    }
+
+    let time = Time::from_hms(
+        number(9..11)? as u8,
+        number(11..13)? as u8,
+        number(13..15)? as u8,
+    )
+    .map_err(|_| invalid())?;
+    let date_time = PrimitiveDateTime::new(date, time);
+
+    let to_local = |date_time: OffsetDateTime| {
+        let local = date_time.to_timezone(local_timezone);
+        PrimitiveDateTime::new(local.date(), local.time())
+    };
+
+    match (&value[15..], tzid) {
+        ("", None) => Ok((date_time, false)),
+        ("", Some(tzid)) => {
+            let Some(timezone) = time_tz::timezones::get_by_name(tzid) else {
+                tracing::warn!(?tzid, "Unknown timezone, using local time");
+                return Ok((date_time, false));
+            };
+
+            // Times skipped by a daylight saving time change are moved past the gap, which is an
+            // hour nearly everywhere
+            let zoned = date_time
+                .assume_timezone(timezone)
+                .take_first()
+                .or_else(|| {
+                    (date_time + time::Duration::HOUR)
+                        .assume_timezone(timezone)
+                        .take_first()
+                })
+                .ok_or_else(invalid)?;
+            Ok((to_local(zoned), false))
+        }
+        ("Z", _) => Ok((to_local(date_time.assume_utc()), false)),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_recurrence_rule(
+    value: &str,
+    local_timezone: &Tz,
+) -> Result<RecurrenceRule, CalendarError> {
+    let invalid = || CalendarError::InvalidRecurrenceRule(value.to_string());
+
+    let mut frequency = None;
+    let mut interval = 1;
+    let mut count = None;
+    let mut until = None;
+    let mut by_day = Vec::new();
+
+    for part in value.split(';') {
+        let (key, part_value) = part.split_once('=').ok_or_else(invalid)?;
+
+        match key {
+            "FREQ" => {
+                frequency = Some(match part_value {
+                    "DAILY" => Frequency::Daily,
+                    "WEEKLY" => Frequency::Weekly,
+                    "MONTHLY" => Frequency::Monthly,
+                    "YEARLY" => Frequency::Yearly,
+                    _ => return Err(invalid()),
+                })
+            }
+            "INTERVAL" => interval = part_value.parse().map_err(|_| invalid())?,
+            "COUNT" => count = Some(part_value.parse().map_err(|_| invalid())?),
+            "UNTIL" => until = Some(parse_date_time(part_value, None, local_timezone)?.0),
+            "BYDAY" => {
+                for day in part_value.split(',') {
+                    by_day.push(match day {
+                        "MO" => Weekday::Monday,
+                        "TU" => Weekday::Tuesday,
+                        "WE" => Weekday::Wednesday,
+                        "TH" => Weekday::Thursday,
+                        "FR" => Weekday::Friday,
+                        "SA" => Weekday::Saturday,
+                        "SU" => Weekday::Sunday,
+                        _ => return Err(invalid()),
+                    });
+                }
+            }
+            "WKST" => {}
+            _ => return Err(invalid()),
+        }
+    }
+
+    let frequency = frequency.ok_or_else(invalid)?;
+    if !by_day.is_empty() && frequency != Frequency::Weekly {
+        return Err(invalid());
+    }
+
+    Ok(RecurrenceRule {
+        frequency,
+        interval,
+        count,
+        until,
+        by_day,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::datetime;
+    use time_tz::timezones::db::europe::BERLIN;
+    use time_tz::timezones::db::europe::LONDON;
+
+    use super::next_appointment;
+    use super::parse_events;
+
+    const CALENDAR: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+BEGIN:VEVENT\r
+UID:standup\r
+SUMMARY:Standup\r
+DTSTART;TZID=Europe/Berlin:20251006T093000\r
+RRULE:FREQ=WEEKLY;BYDAY=MO,WE,FR\r
+EXDATE;TZID=Europe/Berlin:20251022T093000\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:standup\r
+RECURRENCE-ID;TZID=Europe/Berlin:20251020T093000\r
+SUMMARY:Standup (moved)\r
+DTSTART;TZID=Europe/Berlin:20251020T140000\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:birthday\r
+SUMMARY:Birthday\r
+DTSTART;VALUE=DATE:20000229\r
+RRULE:FREQ=YEARLY\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+    const HOLIDAY: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+BEGIN:VEVENT\r
+UID:holiday\r
+SUMMARY:Holiday\r
+DTSTART;VALUE=DATE:20251003\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+    #[test]
+    fn test_weekly_by_day() {
+        let events = parse_events(CALENDAR, BERLIN).unwrap();
+        let next = next_appointment(
+            &events,
+            datetime!(2025-10-17 10:00),
+            datetime!(2025-11-17 10:00),
+        )
+        .unwrap();
+        assert_eq!(next.summary, "Standup (moved)");
+        assert_eq!(next.start, datetime!(2025-10-20 14:00));
+    }
+
+    #[test]
+    fn test_exdate() {
+        let events = parse_events(CALENDAR, BERLIN).unwrap();
+        let next = next_appointment(
+            &events,
+            datetime!(2025-10-20 15:00),
+            datetime!(2025-11-17 10:00),
+        )
+        .unwrap();
+        assert_eq!(next.start, datetime!(2025-10-24 09:30));
+    }
+
+    #[test]
+    fn test_yearly_skips_missing_days() {
+        let events = parse_events(CALENDAR, BERLIN).unwrap();
+        let next = next_appointment(
+            &events[2..],
+            datetime!(2025-01-01 0:00),
+            datetime!(2030-01-01 0:00),
+        )
+        .unwrap();
+        assert!(next.all_day);
+        assert_eq!(next.start, datetime!(2028-02-29 0:00));
+    }
+
+    #[test]
+    fn test_all_day_today() {
+        let events = parse_events(HOLIDAY, BERLIN).unwrap();
+        let now = datetime!(2025-10-03 15:00);
+        let next = next_appointment(&events, now, datetime!(2025-10-10 15:00)).unwrap();
+        assert_eq!(next.start, datetime!(2025-10-03 0:00));
+        assert_eq!(next.when(now), "Today");
+
+        let tomorrow = datetime!(2025-10-04 0:00);
+        assert!(next_appointment(&events, tomorrow, datetime!(2025-10-10 15:00)).is_none());
+    }
+
+    #[test]
+    fn test_tzid_is_converted_to_local_time() {
+        let events = parse_events(CALENDAR, LONDON).unwrap();
+        let next = next_appointment(
+            &events,
+            datetime!(2025-10-17 10:00),
+            datetime!(2025-11-17 10:00),
+        )
+        .unwrap();
+        assert_eq!(next.start, datetime!(2025-10-20 13:00));
+
+        // After the change to winter time in Berlin, on 2025-10-26
+        let next = next_appointment(
+            &events[..1],
+            datetime!(2025-10-27 0:00),
+            datetime!(2025-11-17 10:00),
+        )
+        .unwrap();
+        assert_eq!(next.start, datetime!(2025-10-27 08:30));
+    }
+}
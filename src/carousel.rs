@@ -0,0 +1,141 @@
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::config::CarouselConfig;
+use crate::config::PageKind;
+use crate::config::ScheduleConfig;
+use crate::scheduler::Schedule;
+
+#[derive(Debug)]
+pub enum Page {
+    Time,
+    Date(time::format_description::OwnedFormatItem),
+    Sensor { topic: String },
+    Calendar,
+    Sun,
+    Moon,
+    Alarm(Vec<Schedule>),
+}
+
+/// Schedules which page is shown, starting with the time page
+#[derive(Debug)]
+pub struct Carousel {
+    pages: Vec<(Page, Duration)>,
+    current: usize,
+    page_started: Instant,
+}
+
+impl Carousel {
+    pub fn new(
+        config: Option<&CarouselConfig>,
+        schedules: &[ScheduleConfig],
+    ) -> Result<Self, crate::error::Error> {
+        let mut pages = vec![];
+
+        match config {
+            None => pages.push((Page::Time, Duration::MAX)),
+            Some(config) => {
+                pages.push((Page::Time, config.time_dwell));
+
+                for page in config.pages.iter() {
+                    let content = match &page.kind {
+                        PageKind::Date { format } => Page::Date(
+                            time::format_description::parse_owned::<1>(format)
+                                .map_err(crate::error::Error::TimeFormatDescription)?,
+                        ),
+                        PageKind::Sensor { topic } => Page::Sensor {
+                            topic: topic.clone(),
+                        },
+                        PageKind::Calendar => Page::Calendar,
+                        PageKind::Sun => Page::Sun,
+                        PageKind::Moon => Page::Moon,
+                        PageKind::Alarm { schedules: names } => Page::Alarm(
+                            crate::scheduler::load(schedules)?
+                                .into_iter()
+                                .filter(|schedule| {
+                                    names.is_empty() || names.iter().any(|n| n == schedule.name())
+                                })
+                                .collect(),
+                        ),
+                    };
+                    pages.push((content, page.dwell));
+                }
+            }
+        }
+
+        Ok(Self {
+            pages,
+            current: 0,
+            page_started: Instant::now(),
+        })
+    }
+
+    /// The page to show at `now`, switching to the next page if the current one was shown long
+    /// enough
+    pub fn current(&mut self, now: Instant) -> &Page {
+        let (_, dwell) = &self.pages[self.current];
+        if now.saturating_duration_since(self.page_started) >= *dwell {
+            self.current = (self.current + 1) % self.pages.len();
+            self.page_started = now;
+            tracing::debug!(page = ?self.pages[self.current].0, "Switching carousel page");
+        }
+
+        &self.pages[self.current].0
+    }
+
+    /// Start over with the time page
+    pub fn reset(&mut self, now: Instant) {
+        self.current = 0;
+        self.page_started = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use std::time::Instant;
+
+    use super::Carousel;
+    use super::Page;
+    use crate::config::CarouselConfig;
+    use crate::config::PageConfig;
+    use crate::config::PageKind;
+
+    #[test]
+    fn test_cycles_through_pages() {
+        let config = CarouselConfig {
+            time_dwell: Duration::from_secs(30),
+            pages: vec![PageConfig {
+                dwell: Duration::from_secs(5),
+                kind: PageKind::Sensor {
+                    topic: String::from("sensors/outdoor/temp"),
+                },
+            }],
+        };
+        let mut carousel = Carousel::new(Some(&config), &[]).unwrap();
+        let start = Instant::now();
+        carousel.reset(start);
+
+        assert!(std::matches!(carousel.current(start), Page::Time));
+        let sensor_start = start + Duration::from_secs(30);
+        assert!(std::matches!(
+            carousel.current(sensor_start),
+            Page::Sensor { .. }
+        ));
+        assert!(std::matches!(
+            carousel.current(sensor_start + Duration::from_secs(4)),
+            Page::Sensor { .. }
+        ));
+        assert!(std::matches!(
+            carousel.current(sensor_start + Duration::from_secs(5)),
+            Page::Time
+        ));
+    }
+
+    #[test]
+    fn test_time_only_without_config() {
+        let mut carousel = Carousel::new(None, &[]).unwrap();
+        let far_future = Instant::now() + Duration::from_secs(60 * 60 * 24 * 365);
+        assert!(std::matches!(carousel.current(far_future), Page::Time));
+    }
+}
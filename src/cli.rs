@@ -17,4 +17,17 @@ pub struct Cli {
 pub enum Command {
     Run,
     VerifyConfig,
+
+    /// Show the next times the scheduled events are sent
+    ListSchedules {
+        /// How many times to show per schedule
+        #[clap(long, default_value_t = 5)]
+        count: usize,
+    },
+
+    /// Print the JSON Schema of events sent via MQTT
+    EventSchema {
+        #[clap(long, value_enum, default_value = "2")]
+        schema_version: crate::schema::Version,
+    },
 }
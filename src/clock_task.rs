@@ -1,67 +1,197 @@
-use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 
+use embedded_graphics::geometry::Dimensions;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::Rgb888;
 use embedded_graphics::prelude::DrawTarget;
+use embedded_graphics::prelude::Point;
+use embedded_graphics::text::Baseline;
 use embedded_graphics::text::Text;
 use embedded_graphics::Drawable;
-use rgb::RGB8;
-use smart_leds_matrix::layout::Layout;
-use smart_leds_matrix::SmartLedMatrix;
-use smart_leds_trait::SmartLedsWrite;
 use tokio::sync::Mutex;
 use tokio_util::sync::CancellationToken;
 
+use crate::calendar::Appointment;
+use crate::carousel::Carousel;
+use crate::carousel::Page;
+use crate::config::CalendarConfig;
 use crate::config::Font;
+use crate::config::LocationConfig;
+use crate::config::SensorLineConfig;
+use crate::layer::Layer;
+use crate::layer::Layers;
+use crate::scheduler::Schedule;
+use crate::sensor::Reading;
+use crate::sensor::SensorValues;
+use crate::state::ClockRunning;
+use crate::widget::Icon;
+use crate::widget::WidgetSlot;
 
-pub struct ClockTask<T, L, const SIZE: usize>
-where
-    T: SmartLedsWrite,
-    L: Layout,
-    <T as SmartLedsWrite>::Color: From<RGB8>,
-{
+/// Draws the clock and widgets into their layers
+pub struct ClockTask {
     interval: std::time::Duration,
-    running: Arc<AtomicBool>,
+    running: Arc<ClockRunning>,
     cancellation_token: CancellationToken,
-    matrix: Arc<Mutex<SmartLedMatrix<T, L, SIZE>>>,
+    layers: Arc<Mutex<Layers>>,
+    widgets: Arc<Mutex<WidgetSlot>>,
+    sensor_values: Arc<Mutex<SensorValues>>,
+    next_appointment: Arc<Mutex<Option<Appointment>>>,
     time_offset: embedded_graphics::prelude::Point,
     time_font: Font,
+    sensor_line: Option<SensorLineConfig>,
+    carousel: Carousel,
+    calendar: Option<CalendarConfig>,
+    location: Option<LocationConfig>,
 }
 
-impl<T, L, const SIZE: usize> ClockTask<T, L, SIZE>
-where
-    T: SmartLedsWrite,
-    L: Layout,
-    <T as SmartLedsWrite>::Color: From<RGB8>,
-    crate::error::Error: From<<T as SmartLedsWrite>::Error>,
-{
+impl ClockTask {
     pub fn new(
-        running: Arc<AtomicBool>,
+        running: Arc<ClockRunning>,
         cancellation_token: CancellationToken,
-        matrix: Arc<Mutex<SmartLedMatrix<T, L, SIZE>>>,
+        layers: Arc<Mutex<Layers>>,
+        widgets: Arc<Mutex<WidgetSlot>>,
+        sensor_values: Arc<Mutex<SensorValues>>,
+        next_appointment: Arc<Mutex<Option<Appointment>>>,
         config: &crate::config::Config,
-    ) -> Self {
-        Self {
+    ) -> Result<Self, crate::error::Error> {
+        Ok(Self {
             interval: config.display.interval,
             running,
             cancellation_token,
-            matrix,
+            layers,
+            widgets,
+            sensor_values,
+            next_appointment,
             time_offset: embedded_graphics::prelude::Point::new(
                 config.display.time_offset_x.into(),
                 config.display.time_offset_y.into(),
             ),
             time_font: config.display.time_font,
+            sensor_line: config.display.sensor_line.clone(),
+            carousel: Carousel::new(config.display.carousel.as_ref(), &config.schedules)?,
+            calendar: config.calendar.clone(),
+            location: config.location,
+        })
+    }
+
+    /// The line to show below the time
+    ///
+    /// An appointment that is about to start takes precedence over the sensor values, which
+    /// are rotated through.
+    async fn line_below_time(
+        &self,
+        now: time::PrimitiveDateTime,
+        started: std::time::Instant,
+    ) -> Option<Reading> {
+        let sensor_line = self.sensor_line.as_ref()?;
+
+        if let Some(reading) = self.imminent_appointment(now).await {
+            return Some(reading);
+        }
+
+        let readings = self.sensor_values.lock().await.readings();
+        let rotation = started.elapsed().as_millis() / sensor_line.rotate_every.as_millis().max(1);
+        let idx = rotation as usize % readings.len().max(1);
+        readings.into_iter().nth(idx)
+    }
+
+    async fn imminent_appointment(&self, now: time::PrimitiveDateTime) -> Option<Reading> {
+        let calendar = self.calendar.as_ref()?;
+        let appointment = self.next_appointment.lock().await.clone()?;
+
+        (!appointment.all_day && appointment.start - now <= calendar.lead_time).then(|| Reading {
+            text: format!("{} {}", appointment.when(now), appointment.summary),
+            color: calendar.highlight_color.into(),
+        })
+    }
+
+    async fn calendar_content(&self, now: time::PrimitiveDateTime) -> Content {
+        let appointment = self.next_appointment.lock().await.clone();
+        let (Some(calendar), Some(appointment)) = (self.calendar.as_ref(), appointment) else {
+            return Content::Main {
+                text: String::from("--"),
+                color: None,
+                below: None,
+            };
+        };
+
+        let color = if !appointment.all_day && appointment.start - now <= calendar.lead_time {
+            calendar.highlight_color
+        } else {
+            calendar.color
+        };
+
+        Content::Lines {
+            top: appointment.when(now),
+            bottom: appointment.summary,
+            color: color.into(),
+        }
+    }
+
+    /// The next sunrise or sunset
+    fn sun_content(&self, now: time::OffsetDateTime) -> Content {
+        let Some(location) = self.location.as_ref() else {
+            return Content::Main {
+                text: String::from("--"),
+                color: None,
+                below: None,
+            };
+        };
+
+        let today = crate::astro::sun_times(now.date(), location);
+        let tomorrow = now
+            .date()
+            .next_day()
+            .map(|date| crate::astro::sun_times(date, location));
+
+        let next = [
+            ("Rise", today.sunrise),
+            ("Set", today.sunset),
+            ("Rise", tomorrow.and_then(|sun_times| sun_times.sunrise)),
+        ]
+        .into_iter()
+        .find_map(|(label, at)| at.filter(|at| *at > now).map(|at| (label, at)));
+
+        let (top, bottom) = match next {
+            Some((label, at)) => {
+                let at = at.to_offset(now.offset());
+                (
+                    String::from(label),
+                    format!("{:02}:{:02}", at.hour(), at.minute()),
+                )
+            }
+            None => (String::from("Sun"), String::from("--")),
+        };
+
+        Content::Icon {
+            icon: Icon::Sun,
+            top,
+            bottom,
+            color: SUN_COLOR,
         }
     }
 
-    pub fn run(self) -> impl std::future::Future<Output = Result<(), crate::error::Error>> {
+    fn moon_content(&self, now: time::OffsetDateTime) -> Content {
+        let phase = crate::astro::moon_phase(now);
+        let illumination = crate::astro::moon_illumination(phase);
+
+        Content::Icon {
+            icon: Icon::Moon { phase },
+            top: String::from("Moon"),
+            bottom: format!("{:.0}%", illumination * 100.0),
+            color: MOON_COLOR,
+        }
+    }
+
+    pub fn run(mut self) -> impl std::future::Future<Output = Result<(), crate::error::Error>> {
         let mut render_interval = tokio::time::interval(self.interval);
         let time_display_format = time::format_description::parse("[hour]:[minute]").unwrap();
         async move {
             let font = self.time_font.into();
-            let mut clock_rainbow_style = crate::util::rainbow_color_iterator()
-                .map(|color| embedded_graphics::mono_font::MonoTextStyle::new(&font, color));
+            let mut clock_rainbow_style =
+                crate::util::rainbow_color_iterator().map(|color| MonoTextStyle::new(&font, color));
 
-            let mut last_rendered_str = None;
+            let started = std::time::Instant::now();
 
             loop {
                 let Some(_tick) = self
@@ -73,39 +203,216 @@ where
                     break;
                 };
 
-                if self.running.load(std::sync::atomic::Ordering::Relaxed) {
-                    let mut matrix = self.matrix.lock().await;
+                if self.running.is_running() {
+                    let now = std::time::Instant::now();
+
+                    // Widgets take precedence over the clock for as long as they are shown
+                    if let Some(widget) = self.widgets.lock().await.current() {
+                        let mut layers = self.layers.lock().await;
+                        layers.get_mut(Layer::Clock).clear_transparent();
+                        let widgets = layers.get_mut(Layer::Widgets);
+                        widgets.clear(Rgb888::default()).unwrap();
+                        widget.draw(&mut *widgets).unwrap();
+                        tracing::trace!(?widget, "Rendered widget");
+
+                        self.carousel.reset(now);
+                        continue;
+                    }
 
                     let time = time::OffsetDateTime::now_local()
                         .map_err(crate::error::Error::TimeOffset)?;
+                    let local_time = time::PrimitiveDateTime::new(time.date(), time.time());
+
+                    let content = match self.carousel.current(now) {
+                        Page::Time => Content::Main {
+                            text: time
+                                .format(&time_display_format)
+                                .map_err(crate::error::Error::TimeFormatting)?,
+                            color: None,
+                            below: self.line_below_time(local_time, started).await,
+                        },
+
+                        Page::Date(format) => Content::Main {
+                            text: time
+                                .format(format)
+                                .map_err(crate::error::Error::TimeFormatting)?,
+                            color: None,
+                            below: None,
+                        },
+
+                        Page::Sensor { topic } => {
+                            let reading = self.sensor_values.lock().await.reading(topic);
+                            Content::Main {
+                                text: reading
+                                    .as_ref()
+                                    .map_or_else(|| String::from("--"), |r| r.text.clone()),
+                                color: reading.map(|reading| reading.color),
+                                below: None,
+                            }
+                        }
+
+                        Page::Calendar => self.calendar_content(local_time).await,
+                        Page::Sun => self.sun_content(time),
+                        Page::Moon => self.moon_content(time),
+                        Page::Alarm(schedules) => alarm_content(schedules, time),
+                    };
+
+                    let mut layers = self.layers.lock().await;
+                    layers.get_mut(Layer::Widgets).clear_transparent();
+                    let clock = layers.get_mut(Layer::Clock);
+                    clock.clear_transparent();
+
+                    match &content {
+                        Content::Main { text, color, below } => {
+                            let text_style = clock_rainbow_style.next().unwrap();
+                            let text_style = match color {
+                                Some(color) => MonoTextStyle::new(&font, *color),
+                                None => text_style,
+                            };
 
-                    let time_str = time
-                        .format(&time_display_format)
-                        .map_err(crate::error::Error::TimeFormatting)?;
+                            // Draw text to the buffer
+                            Text::new(text, self.time_offset, text_style)
+                                .draw(&mut *clock)
+                                .unwrap();
 
-                    if last_rendered_str.is_some_and(|s| s != time_str) {
-                        matrix
-                            .clear(embedded_graphics::pixelcolor::Rgb888::default())
+                            if let (Some(sensor_line), Some(below)) =
+                                (self.sensor_line.as_ref(), below)
+                            {
+                                Text::new(
+                                    &below.text,
+                                    Point::new(
+                                        sensor_line.offset_x.into(),
+                                        sensor_line.offset_y.into(),
+                                    ),
+                                    MonoTextStyle::new(&crate::widget::WIDGET_FONT, below.color),
+                                )
+                                .draw(&mut *clock)
+                                .unwrap();
+                            }
+                        }
+
+                        Content::Lines { top, bottom, color } => {
+                            let text_style =
+                                MonoTextStyle::new(&crate::widget::WIDGET_FONT, *color);
+                            let line_height = crate::widget::WIDGET_FONT.character_size.height;
+
+                            Text::with_baseline(top, Point::new(0, 1), text_style, Baseline::Top)
+                                .draw(&mut *clock)
+                                .unwrap();
+                            Text::with_baseline(
+                                bottom,
+                                Point::new(0, line_height as i32 + 3),
+                                text_style,
+                                Baseline::Top,
+                            )
+                            .draw(&mut *clock)
                             .unwrap();
-                        matrix.flush()?;
-                    }
+                        }
 
-                    // Draw text to the buffer
-                    Text::new(
-                        &time_str,
-                        self.time_offset,
-                        clock_rainbow_style.next().unwrap(),
-                    )
-                    .draw(&mut *matrix)
-                    .unwrap();
+                        Content::Icon {
+                            icon,
+                            top,
+                            bottom,
+                            color,
+                        } => {
+                            let icon_top = (clock.bounding_box().size.height as i32
+                                - crate::widget::ICON_SIZE as i32)
+                                / 2;
+                            icon.draw(&mut *clock, Point::new(0, icon_top), *color)
+                                .unwrap();
 
-                    matrix.flush()?;
-                    tracing::trace!(?time_str, "Rendered clock");
+                            let text_style =
+                                MonoTextStyle::new(&crate::widget::WIDGET_FONT, *color);
+                            let line_height = crate::widget::WIDGET_FONT.character_size.height;
+                            let text_x = crate::widget::ICON_SIZE as i32 + 1;
 
-                    last_rendered_str = Some(time_str);
+                            Text::with_baseline(
+                                top,
+                                Point::new(text_x, 1),
+                                text_style,
+                                Baseline::Top,
+                            )
+                            .draw(&mut *clock)
+                            .unwrap();
+                            Text::with_baseline(
+                                bottom,
+                                Point::new(text_x, line_height as i32 + 3),
+                                text_style,
+                                Baseline::Top,
+                            )
+                            .draw(&mut *clock)
+                            .unwrap();
+                        }
+                    }
+
+                    tracing::trace!(?content, "Rendered clock");
                 }
             }
             Ok(())
         }
     }
 }
+
+/// The next time one of `schedules` fires
+fn alarm_content(schedules: &[Schedule], now: time::OffsetDateTime) -> Content {
+    let next = schedules
+        .iter()
+        .filter_map(|schedule| Some((schedule, schedule.next_after(now)?)))
+        .min_by_key(|(_, at)| *at);
+
+    let Some((schedule, at)) = next else {
+        return Content::Lines {
+            top: String::from("Alarm"),
+            bottom: String::from("--"),
+            color: ALARM_COLOR,
+        };
+    };
+
+    let at = at.to_offset(now.offset());
+    let bottom = if at.date() == now.date() {
+        format!("{:02}:{:02}", at.hour(), at.minute())
+    } else {
+        format!(
+            "{} {:02}:{:02}",
+            crate::calendar::weekday_abbreviation(at.weekday()),
+            at.hour(),
+            at.minute()
+        )
+    };
+
+    Content::Lines {
+        top: schedule.name().to_string(),
+        bottom,
+        color: ALARM_COLOR,
+    }
+}
+
+/// What is rendered into the clock layer
+#[derive(Debug)]
+enum Content {
+    /// Text in the time font at the time offset, in rainbow colors if `color` is `None`
+    Main {
+        text: String,
+        color: Option<Rgb888>,
+        below: Option<Reading>,
+    },
+
+    /// Two lines of small text
+    Lines {
+        top: String,
+        bottom: String,
+        color: Rgb888,
+    },
+
+    /// An icon on the left, with two lines of small text next to it
+    Icon {
+        icon: Icon,
+        top: String,
+        bottom: String,
+        color: Rgb888,
+    },
+}
+
+const SUN_COLOR: Rgb888 = Rgb888::new(255, 180, 0);
+const MOON_COLOR: Rgb888 = Rgb888::new(180, 180, 150);
+const ALARM_COLOR: Rgb888 = Rgb888::new(255, 80, 40);
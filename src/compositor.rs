@@ -0,0 +1,426 @@
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+use crate::event::Event;
+use crate::event::EventInner;
+use crate::notification::NotificationQueue;
+use crate::state::Mode;
+use crate::state::SharedState;
+use crate::widget::WidgetSlot;
+
+/// An overlay being shown, it is shown for as long as the future is polled
+pub type OverlayFuture = std::pin::Pin<
+    Box<dyn std::future::Future<Output = Result<(), crate::error::Error>> + Send + 'static>,
+>;
+
+/// Shows overlays on the display
+pub trait Player {
+    fn play(&self, event: EventInner) -> OverlayFuture;
+
+    /// Called when an overlay ended or was aborted, to remove it from the display
+    async fn finish(&self);
+}
+
+#[derive(Debug)]
+pub enum Command {
+    /// Show an overlay event, see [`EventInner::is_overlay`]
+    Show(Event),
+    Dismiss {
+        id: Option<String>,
+    },
+    ClearQueue,
+}
+
+enum State {
+    Idle,
+    Showing {
+        /// The event as it was started, to continue it after it was preempted
+        event: Event,
+        started: tokio::time::Instant,
+        id: Option<String>,
+        mode: Mode,
+        overlay: OverlayFuture,
+    },
+}
+
+impl State {
+    /// Completes when the overlay shown ended, never if none is shown
+    async fn shown(&mut self) -> Result<(), crate::error::Error> {
+        match self {
+            State::Showing { overlay, .. } => overlay.as_mut().await,
+            State::Idle => std::future::pending().await,
+        }
+    }
+}
+
+/// Shows overlays one at a time, in order of priority, without blocking the event loop
+///
+/// An overlay with a higher priority than the one shown preempts it, and the rest of the
+/// preempted overlay is queued ahead of overlays with the same priority. Overlays with the same
+/// or a lower priority are queued. An overlay with the same `id` as the one shown replaces it.
+pub struct CompositorTask<P> {
+    player: P,
+    queue: NotificationQueue,
+    state: State,
+    widgets: Arc<Mutex<WidgetSlot>>,
+    clock_state: SharedState,
+    commands: tokio::sync::mpsc::Receiver<Command>,
+    cancellation_token: CancellationToken,
+}
+
+impl<P> CompositorTask<P>
+where
+    P: Player,
+{
+    pub fn new(
+        player: P,
+        widgets: Arc<Mutex<WidgetSlot>>,
+        clock_state: SharedState,
+        commands: tokio::sync::mpsc::Receiver<Command>,
+        cancellation_token: CancellationToken,
+    ) -> Self {
+        Self {
+            player,
+            queue: NotificationQueue::default(),
+            state: State::Idle,
+            widgets,
+            clock_state,
+            commands,
+            cancellation_token,
+        }
+    }
+
+    async fn start(&mut self, event: Event) {
+        if std::matches!(self.state, State::Showing { .. }) {
+            self.player.finish().await;
+        }
+
+        self.state = State::Showing {
+            id: event.event.overlay_id().map(ToString::to_string),
+            mode: Mode::of_overlay(&event.event),
+            overlay: self.player.play(event.event.clone()),
+            started: tokio::time::Instant::now(),
+            event,
+        };
+    }
+
+    async fn stop(&mut self) {
+        self.state = State::Idle;
+        self.player.finish().await;
+    }
+
+    fn publish_state(&self) {
+        let (mode, notification_id) = match &self.state {
+            State::Showing { mode, id, .. } => (*mode, id.clone()),
+            State::Idle => (Mode::Clock, None),
+        };
+        let queue_length = self.queue.len();
+
+        self.clock_state.update(|state| {
+            state.mode = mode;
+            state.notification_id = notification_id;
+            state.queue_length = queue_length;
+        });
+    }
+
+    async fn handle(&mut self, command: Command) {
+        match command {
+            Command::Show(event) => match &self.state {
+                State::Showing { id: Some(id), .. } if event.event.overlay_id() == Some(id) => {
+                    tracing::info!(?event, "Replacing the overlay currently shown");
+                    self.start(event).await;
+                }
+                State::Showing {
+                    event: shown,
+                    started,
+                    ..
+                } if event.priority > shown.priority => {
+                    tracing::info!(?event, "Preempting the overlay currently shown");
+                    let preempted = remaining(shown.clone(), started.elapsed());
+                    self.start(event).await;
+
+                    if let Some(preempted) = preempted {
+                        tracing::debug!(event = ?preempted, "Queueing the rest of the preempted overlay");
+                        self.queue.push_front(preempted);
+                    }
+                }
+                State::Showing { .. } => {
+                    tracing::info!(?event, queued = self.queue.len() + 1, "Queueing overlay");
+                    self.queue.push(event);
+                }
+                State::Idle => self.start(event).await,
+            },
+
+            Command::Dismiss { id } => {
+                let dismiss_overlay = match &self.state {
+                    State::Showing { id: current_id, .. } => id.is_none() || *current_id == id,
+                    State::Idle => false,
+                };
+                if dismiss_overlay {
+                    tracing::info!(?id, "Dismissing the overlay currently shown");
+                    self.stop().await;
+                }
+
+                if let Some(id) = id.as_deref() {
+                    if self.queue.remove(id) {
+                        tracing::info!(?id, "Dismissed queued overlay");
+                    }
+                    self.widgets.lock().await.dismiss(Some(id));
+                } else if !dismiss_overlay {
+                    self.widgets.lock().await.dismiss(None);
+                }
+            }
+
+            Command::ClearQueue => {
+                let cleared = self.queue.clear();
+                tracing::info!(?cleared, "Cleared overlay queue");
+            }
+        }
+    }
+
+    pub async fn run(mut self) -> Result<(), crate::error::Error> {
+        loop {
+            if std::matches!(self.state, State::Idle) {
+                if let Some(event) = self.queue.pop() {
+                    tracing::debug!(queued = self.queue.len(), "Showing next queued overlay");
+                    self.start(event).await;
+                }
+            }
+            self.publish_state();
+
+            tokio::select! {
+                _cancelled = self.cancellation_token.cancelled() => {
+                    tracing::info!("Ending compositor");
+                    break;
+                }
+
+                command = self.commands.recv() => {
+                    let Some(command) = command else {
+                        tracing::info!("Command channel closed, ending compositor");
+                        break;
+                    };
+                    self.handle(command).await;
+                }
+
+                result = State::shown(&mut self.state) => {
+                    self.stop().await;
+                    if let Err(error) = result {
+                        tracing::error!(?error, "Failed to show overlay");
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// What is left of an overlay `event` that was shown for `elapsed`, if anything
+fn remaining(mut event: Event, elapsed: std::time::Duration) -> Option<Event> {
+    let remaining = event
+        .event
+        .overlay_duration()?
+        .checked_sub(elapsed)
+        .filter(|remaining| !remaining.is_zero())?;
+    event.event.set_overlay_duration(remaining);
+    Some(event)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tokio::sync::Mutex;
+    use tokio_util::sync::CancellationToken;
+
+    use super::Command;
+    use super::CompositorTask;
+    use super::OverlayFuture;
+    use super::Player;
+    use crate::event::Event;
+    use crate::event::EventInner;
+    use crate::event::Priority;
+    use crate::state::ClockState;
+    use crate::state::Mode;
+    use crate::state::SharedState;
+
+    /// Shows `Json` overlays by sleeping, and records when they start and end
+    #[derive(Clone, Default)]
+    struct RecordingPlayer {
+        log: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    impl Player for RecordingPlayer {
+        fn play(&self, event: EventInner) -> OverlayFuture {
+            let EventInner::Json { id, sleep_s, .. } = event else {
+                unreachable!("Only Json events are used in tests");
+            };
+            let id = id.unwrap();
+            let log = self.log.clone();
+
+            Box::pin(async move {
+                log.lock().unwrap().push(format!("start {id}"));
+                tokio::time::sleep(Duration::from_secs(sleep_s)).await;
+                log.lock().unwrap().push(format!("end {id}"));
+                Ok(())
+            })
+        }
+
+        async fn finish(&self) {}
+    }
+
+    fn overlay(id: &str, sleep_s: u64, priority: Priority) -> Command {
+        Command::Show(Event {
+            event: EventInner::Json {
+                id: Some(id.to_string()),
+                value: serde_json::Value::Null,
+                sleep_s,
+            },
+            priority,
+        })
+    }
+
+    fn clock_state() -> SharedState {
+        SharedState::new(ClockState {
+            on: true,
+            brightness: 50,
+            mode: Mode::Clock,
+            notification_id: None,
+            queue_length: 0,
+        })
+    }
+
+    /// Send `commands` one second apart, and return the log once everything was shown
+    async fn run(commands: Vec<Command>) -> Vec<String> {
+        let player = RecordingPlayer::default();
+        let (sender, receiver) = tokio::sync::mpsc::channel(10);
+        let cancellation_token = CancellationToken::new();
+        let task = tokio::task::spawn(
+            CompositorTask::new(
+                player.clone(),
+                Arc::new(Mutex::new(Default::default())),
+                clock_state(),
+                receiver,
+                cancellation_token.clone(),
+            )
+            .run(),
+        );
+
+        for command in commands {
+            sender.send(command).await.unwrap();
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        cancellation_token.cancel();
+        task.await.unwrap().unwrap();
+
+        let log = player.log.lock().unwrap().clone();
+        log
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_same_priority_in_arrival_order() {
+        let log = run(vec![
+            overlay("a", 5, Priority::Normal),
+            overlay("b", 5, Priority::Normal),
+            overlay("c", 5, Priority::Normal),
+        ])
+        .await;
+        assert_eq!(
+            log,
+            ["start a", "end a", "start b", "end b", "start c", "end c"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_higher_priority_preempts_and_jumps_queue() {
+        let log = run(vec![
+            overlay("a", 10, Priority::Normal),
+            overlay("low", 5, Priority::Low),
+            overlay("normal", 5, Priority::Normal),
+            overlay("high", 5, Priority::High),
+        ])
+        .await;
+        assert_eq!(
+            log,
+            [
+                "start a",
+                "start high",
+                "end high",
+                "start a",
+                "end a",
+                "start normal",
+                "end normal",
+                "start low",
+                "end low"
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_same_id_replaces_shown_overlay() {
+        let log = run(vec![
+            overlay("a", 10, Priority::Normal),
+            overlay("a", 2, Priority::Low),
+        ])
+        .await;
+        assert_eq!(log, ["start a", "start a", "end a"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dismiss_and_clear_queue() {
+        let log = run(vec![
+            overlay("a", 10, Priority::Normal),
+            overlay("b", 5, Priority::Normal),
+            overlay("c", 5, Priority::Normal),
+            Command::Dismiss {
+                id: Some(String::from("b")),
+            },
+            Command::Dismiss { id: None },
+            overlay("d", 5, Priority::Normal),
+            Command::ClearQueue,
+        ])
+        .await;
+        assert_eq!(log, ["start a", "start c", "end c"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_state_is_published() {
+        let clock_state = clock_state();
+        let state = clock_state.subscribe();
+        let (sender, receiver) = tokio::sync::mpsc::channel(10);
+        let cancellation_token = CancellationToken::new();
+        tokio::task::spawn(
+            CompositorTask::new(
+                RecordingPlayer::default(),
+                Arc::new(Mutex::new(Default::default())),
+                clock_state,
+                receiver,
+                cancellation_token.clone(),
+            )
+            .run(),
+        );
+
+        sender
+            .send(overlay("a", 5, Priority::Normal))
+            .await
+            .unwrap();
+        sender
+            .send(overlay("b", 5, Priority::Normal))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(state.borrow().mode, Mode::Preset);
+        assert_eq!(state.borrow().notification_id.as_deref(), Some("a"));
+        assert_eq!(state.borrow().queue_length, 1);
+
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert_eq!(state.borrow().mode, Mode::Clock);
+        assert_eq!(state.borrow().notification_id, None);
+        assert_eq!(state.borrow().queue_length, 0);
+        cancellation_token.cancel();
+    }
+}
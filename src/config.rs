@@ -2,11 +2,100 @@
 pub struct Config {
     pub display: DisplayConfig,
     pub mqtt: MqttConfig,
+
+    /// Local calendar files to show upcoming appointments from
+    pub calendar: Option<CalendarConfig>,
+
+    /// Where the clock is, to compute sunrise, sunset and moon phase
+    pub location: Option<LocationConfig>,
+
+    /// Events to send on a schedule
+    #[serde(default)]
+    pub schedules: Vec<ScheduleConfig>,
+
+    /// Sequences that can be played with `PlaySequence`
+    #[serde(default)]
+    pub sequences: Vec<SequenceConfig>,
+
+    /// Events that can be sent with `Template`
+    #[serde(default)]
+    pub templates: Vec<TemplateConfig>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct TemplateConfig {
+    pub name: String,
+
+    /// Parameters that can be used as `{param}` in strings of `event`
+    #[serde(default)]
+    pub params: Vec<String>,
+
+    pub event: serde_json::Value,
+
+    /// Priority of the event, if not set the priority of the `Template` event is used
+    pub priority: Option<crate::event::Priority>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct SequenceConfig {
+    pub name: String,
+    pub steps: Vec<crate::event::SequenceStep>,
+
+    #[serde(default = "crate::event::default_loops")]
+    pub loops: u32,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct ScheduleConfig {
+    pub name: String,
+
+    /// Cron expression `minute hour day-of-month month day-of-week`, e.g. `0 7 * * TUE`
+    pub cron: String,
+
+    /// IANA timezone (e.g. `Europe/Berlin`) the cron expression is evaluated in
+    ///
+    /// If not set, the timezone of the system is used.
+    pub timezone: Option<String>,
+
+    pub event: crate::event::EventInner,
+
+    #[serde(default)]
+    pub priority: crate::event::Priority,
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+pub struct LocationConfig {
+    /// Degrees, positive north of the equator
+    pub latitude: f64,
+
+    /// Degrees, positive east of Greenwich
+    pub longitude: f64,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct CalendarConfig {
+    /// iCalendar (`.ics`) files, e.g. synced by vdirsyncer
+    pub files: Vec<camino::Utf8PathBuf>,
+
+    /// How often to re-read the files
+    #[serde(with = "humantime_serde")]
+    pub refresh_every: std::time::Duration,
+
+    /// How far into the future to look for the next appointment
+    #[serde(with = "humantime_serde")]
+    pub lookahead: std::time::Duration,
+
+    /// Appointments starting within this time are highlighted and shown below the time
+    #[serde(with = "humantime_serde")]
+    pub lead_time: std::time::Duration,
+
+    pub color: crate::event::Color,
+    pub highlight_color: crate::event::Color,
 }
 
 #[derive(Debug, serde::Deserialize)]
 pub struct DisplayConfig {
-    pub host: std::net::IpAddr,
+    pub host: Host,
     pub port: u16,
     pub udp_port: u16,
     pub initial_brightness: u8,
@@ -21,6 +110,297 @@ pub struct DisplayConfig {
     pub debounce_turn_on: std::time::Duration,
 
     pub bootstate: Bootstate,
+
+    /// Where to render the values of `mqtt.sensors` below the clock
+    ///
+    /// If not set, sensor values are not shown.
+    pub sensor_line: Option<SensorLineConfig>,
+
+    /// Brightness to apply depending on the time of day
+    ///
+    /// If not set, `initial_brightness` is kept until changed via `SetBrightness`.
+    pub brightness_schedule: Option<BrightnessScheduleConfig>,
+
+    /// Brightness to apply depending on an ambient light sensor
+    ///
+    /// Cannot be combined with `brightness_schedule`.
+    pub auto_brightness: Option<AutoBrightnessConfig>,
+
+    /// Pages to cycle through while idle
+    ///
+    /// If not set, only the time is shown.
+    pub carousel: Option<CarouselConfig>,
+
+    /// Windows in which the display is turned off, as if `TurnOff` was sent at their start and
+    /// `TurnOn` at their end
+    #[serde(default)]
+    pub power_schedule: Vec<TimeWindowConfig>,
+
+    /// Windows in which notifications do not light up the display
+    pub quiet_hours: Option<QuietHoursConfig>,
+
+    /// Opacity of the layers the display is composed of
+    #[serde(default)]
+    pub layer_opacity: LayerOpacityConfig,
+
+    /// How often frames are sent to the display, defaults to `interval`
+    ///
+    /// Effects advance by one step per frame.
+    #[serde(default, with = "humantime_serde")]
+    pub frame_interval: Option<std::time::Duration>,
+
+    /// Effect rendered behind the clock
+    pub background: Option<BackgroundConfig>,
+}
+
+impl DisplayConfig {
+    pub fn frame_interval(&self) -> std::time::Duration {
+        self.frame_interval.unwrap_or(self.interval)
+    }
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+pub struct BackgroundConfig {
+    pub effect: crate::event::Effect,
+
+    /// The same seed always renders the same frames
+    #[serde(default)]
+    pub seed: u64,
+}
+
+/// Opacity of each layer in percent, layers are drawn in this order
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+pub struct LayerOpacityConfig {
+    #[serde(default = "full_opacity")]
+    pub background: u8,
+
+    #[serde(default = "full_opacity")]
+    pub clock: u8,
+
+    #[serde(default = "full_opacity")]
+    pub widgets: u8,
+
+    #[serde(default = "full_opacity")]
+    pub notifications: u8,
+}
+
+impl Default for LayerOpacityConfig {
+    fn default() -> Self {
+        Self {
+            background: full_opacity(),
+            clock: full_opacity(),
+            widgets: full_opacity(),
+            notifications: full_opacity(),
+        }
+    }
+}
+
+fn full_opacity() -> u8 {
+    100
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct TimeWindowConfig {
+    pub from: ScheduleTime,
+
+    /// If this is before `from`, the window ends on the next day
+    pub until: ScheduleTime,
+
+    /// Days on which the window starts, every day if empty
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl From<time::Weekday> for Weekday {
+    fn from(value: time::Weekday) -> Self {
+        match value {
+            time::Weekday::Monday => Weekday::Monday,
+            time::Weekday::Tuesday => Weekday::Tuesday,
+            time::Weekday::Wednesday => Weekday::Wednesday,
+            time::Weekday::Thursday => Weekday::Thursday,
+            time::Weekday::Friday => Weekday::Friday,
+            time::Weekday::Saturday => Weekday::Saturday,
+            time::Weekday::Sunday => Weekday::Sunday,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct QuietHoursConfig {
+    pub mode: QuietHoursMode,
+    pub windows: Vec<TimeWindowConfig>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+pub enum QuietHoursMode {
+    /// Notifications are shown once the quiet hours end
+    Queue,
+
+    /// Notifications are dropped
+    Suppress,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct BrightnessScheduleConfig {
+    /// Whether to fade linearly between points, or to switch at each point
+    pub interpolate: bool,
+
+    /// How often to re-apply the schedule
+    #[serde(with = "humantime_serde")]
+    pub interval: std::time::Duration,
+
+    /// A brightness set via `SetBrightness` is kept until the next point, or at most this long
+    #[serde(default, with = "humantime_serde")]
+    pub override_timeout: Option<std::time::Duration>,
+
+    pub points: Vec<BrightnessPointConfig>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct BrightnessPointConfig {
+    #[serde(flatten)]
+    pub at: ScheduleTime,
+    pub brightness: u8,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct AutoBrightnessConfig {
+    /// Topic the sensor publishes the illuminance in lux to
+    pub topic: String,
+
+    /// JSON pointer (e.g. `/illuminance`) to the value in a JSON payload
+    ///
+    /// If not set, the whole payload is parsed as number.
+    pub json_pointer: Option<String>,
+
+    /// How often to re-apply the brightness
+    #[serde(with = "humantime_serde")]
+    pub interval: std::time::Duration,
+
+    /// Share of the previous smoothed illuminance kept on every interval, between `0.0` (no
+    /// smoothing) and `1.0`
+    pub smoothing: f32,
+
+    /// Brightness changes smaller than this are not applied
+    pub hysteresis: u8,
+
+    /// How long a brightness set via `SetBrightness` is kept
+    #[serde(with = "humantime_serde")]
+    pub override_timeout: std::time::Duration,
+
+    /// Brightness at certain illuminances, interpolated linearly in between
+    pub curve: Vec<LuxPointConfig>,
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+pub struct LuxPointConfig {
+    pub lux: f32,
+    pub brightness: u8,
+}
+
+/// A time of day, either fixed or relative to sunrise or sunset at `location`
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(untagged)]
+pub enum ScheduleTime {
+    Clock {
+        /// `HH:MM`
+        #[serde(deserialize_with = "deserialize_clock_time")]
+        at: time::Time,
+    },
+
+    Sun {
+        sun: SunEvent,
+
+        /// Minutes after (positive) or before (negative) the sun event
+        #[serde(default)]
+        offset_minutes: i32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+pub enum SunEvent {
+    Sunrise,
+    Sunset,
+}
+
+fn deserialize_clock_time<'de, D>(deserializer: D) -> Result<time::Time, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = <String as serde::Deserialize>::deserialize(deserializer)?;
+
+    value
+        .split_once(':')
+        .and_then(|(hour, minute)| {
+            time::Time::from_hms(hour.parse().ok()?, minute.parse().ok()?, 0).ok()
+        })
+        .ok_or_else(|| {
+            serde::de::Error::custom(format!("Invalid time of day '{value}', expected HH:MM"))
+        })
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct CarouselConfig {
+    /// How long to show the time before switching to the first page
+    #[serde(with = "humantime_serde")]
+    pub time_dwell: std::time::Duration,
+
+    pub pages: Vec<PageConfig>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct PageConfig {
+    #[serde(with = "humantime_serde")]
+    pub dwell: std::time::Duration,
+
+    #[serde(flatten)]
+    pub kind: PageKind,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(tag = "page")]
+pub enum PageKind {
+    /// The current date, `format` is a `time` format description like `[day].[month]`
+    Date { format: String },
+
+    /// The latest value of the sensor subscribed to `topic`
+    Sensor { topic: String },
+
+    /// The next appointment from `calendar`
+    Calendar,
+
+    /// The next sunrise or sunset at `location`
+    Sun,
+
+    /// The current moon phase
+    Moon,
+
+    /// The next time one of `schedules` fires, any schedule if empty
+    Alarm {
+        #[serde(default)]
+        schedules: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct SensorLineConfig {
+    pub offset_x: u8,
+    pub offset_y: u8,
+
+    /// How long to show each sensor value before rotating to the next one
+    #[serde(with = "humantime_serde")]
+    pub rotate_every: std::time::Duration,
 }
 
 #[derive(Debug, Clone, Copy, serde::Deserialize)]
@@ -56,7 +436,7 @@ impl From<Font> for embedded_graphics::mono_font::MonoFont<'static> {
 
 #[derive(Debug, Clone, serde::Deserialize)]
 pub struct MqttConfig {
-    pub host: std::net::IpAddr,
+    pub host: Host,
     pub port: u16,
     pub qos: Qos,
     pub client_name: String,
@@ -68,6 +448,120 @@ pub struct MqttConfig {
     pub keep_alive: std::time::Duration,
 
     pub topic_prefix: String,
+
+    /// Topics to subscribe to for values shown on the clock face
+    #[serde(default)]
+    pub sensors: Vec<SensorConfig>,
+
+    /// Publish discovery configs, so the clock shows up in Home Assistant
+    pub home_assistant: Option<HomeAssistantConfig>,
+
+    /// Connect to the broker via TLS
+    pub tls: Option<MqttTlsConfig>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct MqttTlsConfig {
+    /// PEM file with the certificate authorities the broker certificate is verified against
+    pub ca_file: camino::Utf8PathBuf,
+
+    /// PEM file with the certificate to authenticate to the broker with
+    pub client_cert_file: Option<camino::Utf8PathBuf>,
+
+    /// PEM file with the private key of `client_cert_file`
+    pub client_key_file: Option<camino::Utf8PathBuf>,
+
+    /// Name the broker certificate has to be valid for and that is sent as server name (SNI),
+    /// defaults to `host`
+    ///
+    /// The broker is connected to via this name instead of `host`, so it has to resolve to the
+    /// broker.
+    pub server_name: Option<Host>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct HomeAssistantConfig {
+    #[serde(default = "default_discovery_prefix")]
+    pub discovery_prefix: String,
+
+    /// Identifies the clock in Home Assistant, only `[a-zA-Z0-9_-]` are allowed
+    pub node_id: String,
+
+    /// How long texts sent via the notification entity are shown
+    #[serde(default = "default_notification_duration", with = "humantime_serde")]
+    pub notification_duration: std::time::Duration,
+
+    #[serde(default = "default_notification_color")]
+    pub notification_color: crate::event::Color,
+
+    #[serde(default = "default_notification_x")]
+    pub notification_x: u8,
+
+    #[serde(default = "default_notification_y")]
+    pub notification_y: u8,
+
+    /// How long effects selected via the effect entity are shown
+    #[serde(default = "default_effect_duration", with = "humantime_serde")]
+    pub effect_duration: std::time::Duration,
+}
+
+fn default_discovery_prefix() -> String {
+    String::from("homeassistant")
+}
+
+fn default_notification_duration() -> std::time::Duration {
+    std::time::Duration::from_secs(10)
+}
+
+fn default_notification_color() -> crate::event::Color {
+    crate::event::Color {
+        r: 255,
+        g: 255,
+        b: 255,
+    }
+}
+
+fn default_notification_x() -> u8 {
+    1
+}
+
+fn default_notification_y() -> u8 {
+    10
+}
+
+fn default_effect_duration() -> std::time::Duration {
+    std::time::Duration::from_secs(60)
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct SensorConfig {
+    pub topic: String,
+
+    /// JSON pointer (e.g. `/temperature`) to the value in a JSON payload
+    ///
+    /// If not set, the whole payload is parsed as number.
+    pub json_pointer: Option<String>,
+
+    #[serde(default)]
+    pub unit: String,
+
+    /// How the value is rendered, `{value}` and `{unit}` are replaced
+    #[serde(default = "default_sensor_format")]
+    pub format: String,
+
+    /// Number of decimal places of the rendered value
+    #[serde(default)]
+    pub decimals: usize,
+
+    /// Color used if no threshold matches
+    pub color: crate::event::Color,
+
+    #[serde(default)]
+    pub thresholds: Vec<crate::event::ColorThreshold>,
+}
+
+fn default_sensor_format() -> String {
+    String::from("{value}{unit}")
 }
 
 #[derive(Debug, Clone, Copy, serde::Deserialize)]
@@ -98,7 +592,99 @@ impl Config {
                     source,
                 })?;
 
-        toml::from_str(&config_str).map_err(ConfigError::ParsingConfig)
+        let config: Self = toml::from_str(&config_str).map_err(ConfigError::ParsingConfig)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check that carousel pages only refer to sensors and schedules that are configured
+    fn validate(&self) -> Result<(), ConfigError> {
+        let Some(carousel) = self.display.carousel.as_ref() else {
+            return Ok(());
+        };
+
+        for page in carousel.pages.iter() {
+            match &page.kind {
+                PageKind::Sensor { topic } => {
+                    if !self
+                        .mqtt
+                        .sensors
+                        .iter()
+                        .any(|sensor| sensor.topic == *topic)
+                    {
+                        return Err(ConfigError::UnknownSensor(topic.clone()));
+                    }
+                }
+                PageKind::Alarm { schedules } => {
+                    for name in schedules.iter() {
+                        if !self.schedules.iter().any(|schedule| schedule.name == *name) {
+                            return Err(ConfigError::UnknownSchedule(name.clone()));
+                        }
+                    }
+                }
+                PageKind::Date { .. } | PageKind::Calendar | PageKind::Sun | PageKind::Moon => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A hostname or an IP address
+///
+/// Hostnames are resolved whenever a connection is made.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub enum Host {
+    Ip(std::net::IpAddr),
+    Name(String),
+}
+
+impl TryFrom<String> for Host {
+    type Error = ConfigError;
+
+    fn try_from(host: String) -> Result<Self, Self::Error> {
+        let ip = host
+            .strip_prefix('[')
+            .and_then(|host| host.strip_suffix(']'))
+            .unwrap_or(&host);
+        if let Ok(ip) = ip.parse() {
+            return Ok(Host::Ip(ip));
+        }
+
+        let is_label = |label: &str| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        };
+        if host.len() > 253 || !host.trim_end_matches('.').split('.').all(is_label) {
+            return Err(ConfigError::InvalidHost(host));
+        }
+
+        Ok(Host::Name(host))
+    }
+}
+
+impl Host {
+    /// The host without brackets around IPv6 addresses, as used for lookups and as TLS server name
+    pub fn unbracketed(&self) -> String {
+        match self {
+            Host::Ip(ip) => ip.to_string(),
+            Host::Name(name) => name.clone(),
+        }
+    }
+}
+
+/// Formats IPv6 addresses in brackets, as used in URLs and `host:port`
+impl std::fmt::Display for Host {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Host::Ip(std::net::IpAddr::V6(ip)) => write!(f, "[{ip}]"),
+            Host::Ip(ip) => ip.fmt(f),
+            Host::Name(name) => name.fmt(f),
+        }
     }
 }
 
@@ -113,4 +699,99 @@ pub enum ConfigError {
 
     #[error(transparent)]
     ParsingConfig(#[from] toml::de::Error),
+
+    #[error("'{}' is neither a hostname nor an IP address", .0)]
+    InvalidHost(String),
+
+    #[error("Carousel page shows sensor '{}', which is not configured in mqtt.sensors", .0)]
+    UnknownSensor(String),
+
+    #[error("Carousel page shows schedule '{}', which is not configured", .0)]
+    UnknownSchedule(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+    use super::ConfigError;
+    use super::Host;
+
+    const CAROUSEL_CONFIG: &str = r#"
+        schedules = [
+            { name = "wake-up", cron = "0 7 * * *", event = "TurnOn" },
+        ]
+
+        [mqtt]
+        host = "mqtt.lan"
+        port = 1883
+        qos = "AtMostOnce"
+        client_name = "clock"
+        topic_prefix = "clock"
+        keep_alive = "10s"
+        sensors = [
+            { topic = "sensors/outdoor/temp", color = { r = 100, g = 100, b = 100 } },
+        ]
+
+        [display]
+        host = "wled-clock.local"
+        port = 4048
+        udp_port = 6969
+        initial_brightness = 20
+        interval = "1s"
+        time_font = "Font6x9"
+        time_offset_x = 1
+        time_offset_y = 10
+        debounce_turn_on = "1s"
+        bootstate = "on"
+
+        [display.carousel]
+        time_dwell = "30s"
+        pages = [
+            { page = "Sensor", topic = "sensors/outdoor/temp", dwell = "5s" },
+            { page = "Alarm", schedules = ["wake-up"], dwell = "5s" },
+        ]
+    "#;
+
+    #[test]
+    fn test_validate_carousel_pages() {
+        let config: Config = toml::from_str(CAROUSEL_CONFIG).unwrap();
+        assert!(config.validate().is_ok());
+
+        let unknown_sensor = CAROUSEL_CONFIG.replace(
+            "page = \"Sensor\", topic = \"sensors/outdoor",
+            "page = \"Sensor\", topic = \"sensors/indoor",
+        );
+        let config: Config = toml::from_str(&unknown_sensor).unwrap();
+        assert!(std::matches!(
+            config.validate(),
+            Err(ConfigError::UnknownSensor(topic)) if topic == "sensors/indoor/temp"
+        ));
+
+        let unknown_schedule =
+            CAROUSEL_CONFIG.replace("schedules = [\"wake-up\"]", "schedules = [\"nap\"]");
+        let config: Config = toml::from_str(&unknown_schedule).unwrap();
+        assert!(std::matches!(
+            config.validate(),
+            Err(ConfigError::UnknownSchedule(name)) if name == "nap"
+        ));
+    }
+
+    #[test]
+    fn test_host() {
+        let host = |host: &str| Host::try_from(host.to_string()).map(|host| host.to_string());
+
+        assert_eq!(host("192.168.178.42").unwrap(), "192.168.178.42");
+        assert_eq!(host("::1").unwrap(), "[::1]");
+        assert_eq!(host("[::1]").unwrap(), "[::1]");
+        assert_eq!(
+            Host::try_from(String::from("[::1]")).unwrap().unbracketed(),
+            "::1"
+        );
+        assert_eq!(host("wled-clock.local").unwrap(), "wled-clock.local");
+        assert_eq!(host("mqtt").unwrap(), "mqtt");
+        assert!(host("").is_err());
+        assert!(host("mqtt.lan:1883").is_err());
+        assert!(host("mqtt..lan").is_err());
+        assert!(host("http://mqtt.lan").is_err());
+    }
 }
@@ -0,0 +1,596 @@
+use std::sync::Arc;
+
+use embedded_graphics::geometry::Point;
+use embedded_graphics::geometry::Size;
+use embedded_graphics::pixelcolor::Rgb888;
+use embedded_graphics::prelude::DrawTarget;
+use embedded_graphics::prelude::RgbColor;
+use embedded_graphics::Pixel;
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+use crate::event::Effect;
+use crate::layer::FrameBuffer;
+use crate::layer::Layer;
+use crate::layer::Layers;
+use crate::state::ClockRunning;
+
+/// Acceleration of fireworks, in pixels per frame squared
+const GRAVITY: f32 = 0.04;
+
+/// Sparks per exploding rocket
+const SPARKS: usize = 16;
+
+/// Game of Life starts over after this many generations, even if it did not settle
+const MAX_GENERATIONS: u32 = 500;
+
+/// xorshift64*, so that an effect renders the same frames for the same seed
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // One splitmix64 step, so that similar seeds do not start similarly and the state is
+        // never zero
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        Self((z ^ (z >> 31)).max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniformly distributed in `0..n`
+    fn below(&mut self, n: u32) -> u32 {
+        (((self.next() >> 32) * u64::from(n)) >> 32) as u32
+    }
+
+    /// Uniformly distributed in `0.0..1.0`
+    fn unit(&mut self) -> f32 {
+        (self.next() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Raindrop {
+    y: u32,
+
+    /// Frames it takes to fall by one row
+    slowness: u32,
+}
+
+/// A star at `x`, `y` in `-1.0..1.0` at distance `z` in `0.0..=1.0`
+#[derive(Debug, Clone, Copy)]
+struct Star {
+    x: f32,
+    y: f32,
+    z: f32,
+}
+
+impl Star {
+    fn random(rng: &mut Rng, z: f32) -> Self {
+        Self {
+            x: rng.unit() * 2.0 - 1.0,
+            y: rng.unit() * 2.0 - 1.0,
+            z,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Particle {
+    x: f32,
+    y: f32,
+    vx: f32,
+    vy: f32,
+    color: Rgb888,
+
+    /// Fades from 1.0 to 0.0
+    life: f32,
+}
+
+#[derive(Debug)]
+enum State {
+    Plasma {
+        phase: f32,
+    },
+    Fire {
+        heat: Vec<u8>,
+    },
+    MatrixRain {
+        drops: Vec<Option<Raindrop>>,
+        glow: Vec<u8>,
+    },
+    Starfield {
+        stars: Vec<Star>,
+    },
+    GameOfLife {
+        cells: Vec<bool>,
+        previous: Vec<bool>,
+        generation: u32,
+    },
+    Fireworks {
+        rockets: Vec<Particle>,
+        sparks: Vec<Particle>,
+    },
+}
+
+/// Renders an [`Effect`] frame by frame
+///
+/// The frames only depend on the effect, the seed and the size.
+#[derive(Debug)]
+pub struct EffectRenderer {
+    size: Size,
+    rng: Rng,
+    frame: u32,
+    state: State,
+}
+
+impl EffectRenderer {
+    pub fn new(effect: Effect, seed: u64, size: Size) -> Self {
+        let mut rng = Rng::new(seed);
+        let pixels = (size.width * size.height) as usize;
+
+        let state = match effect {
+            Effect::Plasma => State::Plasma {
+                phase: rng.unit() * std::f32::consts::TAU,
+            },
+            Effect::Fire => State::Fire {
+                heat: vec![0; pixels],
+            },
+            Effect::MatrixRain => State::MatrixRain {
+                drops: vec![None; size.width as usize],
+                glow: vec![0; pixels],
+            },
+            Effect::Starfield => State::Starfield {
+                stars: (0..(pixels / 16).max(4))
+                    .map(|_| {
+                        let z = rng.unit();
+                        Star::random(&mut rng, z)
+                    })
+                    .collect(),
+            },
+            Effect::GameOfLife => State::GameOfLife {
+                cells: random_cells(&mut rng, pixels),
+                previous: Vec::new(),
+                generation: 0,
+            },
+            Effect::Fireworks => State::Fireworks {
+                rockets: Vec::new(),
+                sparks: Vec::new(),
+            },
+        };
+
+        Self {
+            size,
+            rng,
+            frame: 0,
+            state,
+        }
+    }
+
+    /// Render the next frame into `target`, every pixel is covered
+    pub fn render(&mut self, target: &mut FrameBuffer) {
+        let width = self.size.width as usize;
+        let height = self.size.height as usize;
+        let rng = &mut self.rng;
+
+        target.clear(Rgb888::BLACK).unwrap();
+
+        match &mut self.state {
+            State::Plasma { phase } => {
+                let t = self.frame as f32 / 8.0 + *phase;
+                let pixels = points(self.size).map(|point| {
+                    let (x, y) = (point.x as f32, point.y as f32);
+                    let value = (x / 4.0 + t).sin()
+                        + (y / 3.0 - t * 0.8).sin()
+                        + ((x + y) / 6.0 + t * 0.6).sin()
+                        + ((x * x + y * y).sqrt() / 4.0 - t * 1.2).sin();
+                    Pixel(point, crate::util::hue_to_rgb(value / 8.0 + t / 16.0))
+                });
+                target.draw_iter(pixels).unwrap();
+            }
+
+            State::Fire { heat } => {
+                // Heat rises from the bottom row and cools down on its way up
+                for y in 0..height.saturating_sub(1) {
+                    for x in 0..width {
+                        let below = |dx: usize, dy: usize| {
+                            let row = (y + dy).min(height - 1);
+                            u32::from(heat[row * width + (x + dx) % width])
+                        };
+                        let average =
+                            (below(0, 1) + below(width - 1, 1) + below(1, 1) + below(0, 2)) / 4;
+                        let cooling = rng.below(48);
+                        heat[y * width + x] = average.saturating_sub(cooling) as u8;
+                    }
+                }
+                for x in 0..width {
+                    heat[(height - 1) * width + x] = 160 + rng.below(96) as u8;
+                }
+
+                let pixels = points(self.size)
+                    .zip(heat.iter())
+                    .map(|(point, heat)| Pixel(point, heat_color(*heat)));
+                target.draw_iter(pixels).unwrap();
+            }
+
+            State::MatrixRain { drops, glow } => {
+                for value in glow.iter_mut() {
+                    *value = (u16::from(*value) * 3 / 4) as u8;
+                }
+
+                for (x, drop) in drops.iter_mut().enumerate() {
+                    match drop {
+                        None if rng.below(100) < 8 => {
+                            *drop = Some(Raindrop {
+                                y: 0,
+                                slowness: 1 + rng.below(2),
+                            });
+                        }
+                        Some(raindrop) if self.frame.is_multiple_of(raindrop.slowness) => {
+                            raindrop.y += 1;
+                        }
+                        _ => {}
+                    }
+
+                    match drop {
+                        Some(raindrop) if raindrop.y as usize >= height => *drop = None,
+                        Some(raindrop) => glow[raindrop.y as usize * width + x] = u8::MAX,
+                        None => {}
+                    }
+                }
+
+                let pixels = points(self.size).zip(glow.iter()).map(|(point, glow)| {
+                    let color = match *glow {
+                        u8::MAX => Rgb888::new(180, 255, 180),
+                        glow => Rgb888::new(0, glow, 0),
+                    };
+                    Pixel(point, color)
+                });
+                target.draw_iter(pixels).unwrap();
+            }
+
+            State::Starfield { stars } => {
+                let center_x = width as f32 / 2.0;
+                let center_y = height as f32 / 2.0;
+
+                for star in stars.iter_mut() {
+                    star.z -= 0.03;
+                    let x = center_x + star.x / star.z * center_x;
+                    let y = center_y + star.y / star.z * center_y;
+
+                    let visible = star.z > 0.05
+                        && (0.0..width as f32).contains(&x)
+                        && (0.0..height as f32).contains(&y);
+                    if !visible {
+                        *star = Star::random(rng, 1.0);
+                        continue;
+                    }
+
+                    let brightness = ((1.0 - star.z) * 255.0) as u8;
+                    target
+                        .draw_iter([Pixel(
+                            Point::new(x as i32, y as i32),
+                            Rgb888::new(brightness, brightness, brightness),
+                        )])
+                        .unwrap();
+                }
+            }
+
+            State::GameOfLife {
+                cells,
+                previous,
+                generation,
+            } => {
+                let alive = |x: usize, y: usize, dx: usize, dy: usize| {
+                    cells[(y + dy) % height * width + (x + dx) % width]
+                };
+
+                let next = (0..height)
+                    .flat_map(|y| (0..width).map(move |x| (x, y)))
+                    .map(|(x, y)| {
+                        let neighbours = [
+                            (width - 1, height - 1),
+                            (0, height - 1),
+                            (1, height - 1),
+                            (width - 1, 0),
+                            (1, 0),
+                            (width - 1, 1),
+                            (0, 1),
+                            (1, 1),
+                        ]
+                        .into_iter()
+                        .filter(|(dx, dy)| alive(x, y, *dx, *dy))
+                        .count();
+
+                        std::matches!((alive(x, y, 0, 0), neighbours), (true, 2) | (_, 3))
+                    })
+                    .collect::<Vec<_>>();
+
+                // Start over once it settled into a still life or a blinker, or died out
+                let settled = next == *cells || next == *previous || !next.contains(&true);
+                if settled || *generation >= MAX_GENERATIONS {
+                    *previous = Vec::new();
+                    *cells = random_cells(rng, width * height);
+                    *generation = 0;
+                } else {
+                    *previous = std::mem::replace(cells, next);
+                    *generation += 1;
+                }
+
+                let color = crate::util::hue_to_rgb(*generation as f32 / 100.0);
+                let pixels = points(self.size)
+                    .zip(cells.iter())
+                    .filter(|(_, alive)| **alive)
+                    .map(|(point, _)| Pixel(point, color));
+                target.draw_iter(pixels).unwrap();
+            }
+
+            State::Fireworks { rockets, sparks } => {
+                if rockets.is_empty() && (sparks.is_empty() || rng.below(100) < 8) {
+                    let apex = height as f32 * (0.5 + rng.unit() * 0.3);
+                    rockets.push(Particle {
+                        x: width as f32 * (0.25 + rng.unit() * 0.5),
+                        y: height as f32 - 1.0,
+                        vx: (rng.unit() - 0.5) * 0.2,
+                        vy: -(2.0 * GRAVITY * apex).sqrt(),
+                        color: Rgb888::new(255, 200, 120),
+                        life: 1.0,
+                    });
+                }
+
+                for rocket in rockets.iter_mut() {
+                    rocket.x += rocket.vx;
+                    rocket.y += rocket.vy;
+                    rocket.vy += GRAVITY;
+
+                    if rocket.vy >= 0.0 {
+                        let color = crate::util::hue_to_rgb(rng.unit());
+                        sparks.extend((0..SPARKS).map(|idx| {
+                            let angle = idx as f32 / SPARKS as f32 * std::f32::consts::TAU
+                                + rng.unit() * 0.3;
+                            let speed = 0.3 + rng.unit() * 0.3;
+                            Particle {
+                                vx: angle.cos() * speed,
+                                vy: angle.sin() * speed,
+                                color,
+                                ..*rocket
+                            }
+                        }));
+                        rocket.life = 0.0;
+                    }
+                }
+                rockets.retain(|rocket| rocket.life > 0.0);
+
+                for spark in sparks.iter_mut() {
+                    spark.x += spark.vx;
+                    spark.y += spark.vy;
+                    spark.vx *= 0.95;
+                    spark.vy = spark.vy * 0.95 + GRAVITY / 2.0;
+                    spark.life -= 0.04;
+                }
+                sparks.retain(|spark| spark.life > 0.0 && spark.y < height as f32);
+
+                let pixels = rockets.iter().chain(sparks.iter()).map(|particle| {
+                    let fade = |channel: u8| (f32::from(channel) * particle.life) as u8;
+                    Pixel(
+                        Point::new(particle.x as i32, particle.y as i32),
+                        Rgb888::new(
+                            fade(particle.color.r()),
+                            fade(particle.color.g()),
+                            fade(particle.color.b()),
+                        ),
+                    )
+                });
+                target.draw_iter(pixels).unwrap();
+            }
+        }
+
+        self.frame = self.frame.wrapping_add(1);
+    }
+}
+
+/// All points of an area of `size`, row by row
+fn points(size: Size) -> impl Iterator<Item = Point> {
+    (0..size.height as i32).flat_map(move |y| (0..size.width as i32).map(move |x| Point::new(x, y)))
+}
+
+fn random_cells(rng: &mut Rng, count: usize) -> Vec<bool> {
+    (0..count).map(|_| rng.below(100) < 35).collect()
+}
+
+/// Black over red and yellow to white
+fn heat_color(heat: u8) -> Rgb888 {
+    match u16::from(heat) * 3 {
+        t @ 0..=255 => Rgb888::new(t as u8, 0, 0),
+        t @ 256..=511 => Rgb888::new(255, (t - 256) as u8, 0),
+        t => Rgb888::new(255, 255, (t - 512) as u8),
+    }
+}
+
+/// Renders `display.background` into the background layer
+pub struct BackgroundTask {
+    renderer: EffectRenderer,
+    interval: std::time::Duration,
+    running: Arc<ClockRunning>,
+    cancellation_token: CancellationToken,
+    layers: Arc<Mutex<Layers>>,
+}
+
+impl BackgroundTask {
+    pub fn new(
+        renderer: EffectRenderer,
+        interval: std::time::Duration,
+        running: Arc<ClockRunning>,
+        cancellation_token: CancellationToken,
+        layers: Arc<Mutex<Layers>>,
+    ) -> Self {
+        Self {
+            renderer,
+            interval,
+            running,
+            cancellation_token,
+            layers,
+        }
+    }
+
+    pub async fn run(mut self) -> Result<(), crate::error::Error> {
+        let mut frame_interval = tokio::time::interval(self.interval);
+
+        loop {
+            let Some(_tick) = self
+                .cancellation_token
+                .run_until_cancelled(frame_interval.tick())
+                .await
+            else {
+                tracing::info!("Ending background effect");
+                break;
+            };
+
+            if self.running.is_running() {
+                let mut layers = self.layers.lock().await;
+                self.renderer.render(layers.get_mut(Layer::Background));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_graphics::geometry::Point;
+    use embedded_graphics::geometry::Size;
+    use embedded_graphics::prelude::RgbColor;
+
+    use super::EffectRenderer;
+    use crate::event::Effect;
+    use crate::layer::FrameBuffer;
+
+    const SIZE: Size = Size::new(16, 8);
+
+    /// The frame after `frames` frames, with one character per pixel from dark to bright
+    fn render(effect: Effect, seed: u64, frames: usize) -> String {
+        const SHADES: &[u8] = b".,:-=+*#%@";
+
+        let mut renderer = EffectRenderer::new(effect, seed, SIZE);
+        let mut frame = FrameBuffer::new(SIZE);
+        for _ in 0..frames {
+            renderer.render(&mut frame);
+        }
+
+        (0..SIZE.height as i32)
+            .map(|y| {
+                (0..SIZE.width as i32)
+                    .map(|x| {
+                        let color = frame.pixel(Point::new(x, y)).unwrap();
+                        let luma = (u32::from(color.r()) * 3
+                            + u32::from(color.g()) * 6
+                            + u32::from(color.b()))
+                            / 10;
+                        char::from(SHADES[luma as usize * SHADES.len() / 256])
+                    })
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn test_same_seed_same_frames() {
+        for effect in [Effect::Fire, Effect::Starfield, Effect::Fireworks] {
+            assert_eq!(render(effect, 1, 30), render(effect, 1, 30));
+            assert_ne!(render(effect, 1, 30), render(effect, 2, 30));
+        }
+    }
+
+    #[test]
+    fn test_plasma() {
+        insta::assert_snapshot!(render(Effect::Plasma, 1, 10), @r"
+        *#%*-----------+
+        #%*=-----------=
+        %#+:----::------
+        #*-----:::------
+        *+-----:::-----=
+        *=------:------*
+        *=------------+%
+        *=:----------+%%
+        ");
+    }
+
+    #[test]
+    fn test_fire() {
+        insta::assert_snapshot!(render(Effect::Fire, 1, 20), @r"
+        --,,::=::,:,::--
+        -::::::-,,-=:--:
+        ++===-::,:-=++++
+        *-=+*%%*+*++=#=*
+        +####%%%*#*%%%#+
+        @@%@#%@*@@@@%@@#
+        @@%%@%%@@@%@@@%%
+        @@%%@@@@@@%@@@@%
+        ");
+    }
+
+    #[test]
+    fn test_matrix_rain() {
+        insta::assert_snapshot!(render(Effect::MatrixRain, 1, 20), @r"
+        ..:.....,..:%...
+        ..-.....,..-....
+        ,.=.....-..=....
+        ,.%....,%..%...,
+        -......,....,..,
+        %,..,.,,....,..,
+        .,..,.,:.,..,,,:
+        .-..-.--.,..:,,-
+        ");
+    }
+
+    #[test]
+    fn test_starfield() {
+        insta::assert_snapshot!(render(Effect::Starfield, 1, 20), @r"
+        .....=..........
+        .............+..
+        +.....%.........
+        .+..............
+        ................
+        ................
+        ................
+        ...........=....
+        ");
+    }
+
+    #[test]
+    fn test_game_of_life() {
+        insta::assert_snapshot!(render(Effect::GameOfLife, 1, 10), @r"
+        *..***.....**..*
+        *.**.*****.*....
+        ......***..*..**
+        ..*.*..*..*....*
+        ....*....*****..
+        .*.**...***.**..
+        ........**......
+        ...*....**.**..*
+        ");
+    }
+
+    #[test]
+    fn test_fireworks() {
+        insta::assert_snapshot!(render(Effect::Fireworks, 1, 25), @r"
+        ......=.===.=...
+        ......=.....=.=.
+        ................
+        .......=...%=...
+        .........=...=..
+        .......=...=....
+        .........=......
+        ................
+        ");
+    }
+}
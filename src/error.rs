@@ -15,14 +15,77 @@ pub enum Error {
     #[error("Error formatting time")]
     TimeFormatting(#[source] time::error::Format),
 
+    #[error("Invalid time format description")]
+    TimeFormatDescription(#[source] time::error::InvalidFormatDescription),
+
     #[error("Failed to bind UDP socket")]
     UDPBind(#[source] std::io::Error),
 
+    #[error("Failed to resolve display '{}'", .host)]
+    ResolvingDisplay {
+        host: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("No address found for display '{}'", .0)]
+    DisplayNotFound(String),
+
     #[error("URL error")]
     Url(#[from] url::ParseError),
 
     #[error("Reqwest error")]
     Reqwest(#[source] reqwest::Error),
+
+    #[error("Times relative to sunrise or sunset require a location in the configuration")]
+    MissingLocation,
+
+    #[error("Brightness schedule and automatic brightness cannot be combined")]
+    ConflictingBrightnessModes,
+
+    #[error("The automatic brightness curve needs at least one point")]
+    EmptyBrightnessCurve,
+
+    #[error("Invalid cron expression in schedule '{}'", .name)]
+    Cron {
+        name: String,
+        #[source]
+        source: croner::errors::CronError,
+    },
+
+    #[error("Unknown timezone '{}'", .0)]
+    UnknownTimezone(String),
+
+    #[error("Failed to determine the timezone of the system")]
+    SystemTimezone(#[source] time_tz::system::Error),
+
+    #[error("Sequences cannot contain sequences")]
+    NestedSequence,
+
+    #[error("Unknown sequence '{}'", .0)]
+    UnknownSequence(String),
+
+    #[error("Unknown template '{}'", .0)]
+    UnknownTemplate(String),
+
+    #[error("Template '{}' uses parameter '{}', which it does not declare", .template, .param)]
+    UndeclaredTemplateParam { template: String, param: String },
+
+    #[error("Template '{}' requires parameter '{}'", .template, .param)]
+    MissingTemplateParam { template: String, param: String },
+
+    #[error("Template '{}' has no parameter '{}'", .template, .param)]
+    UnknownTemplateParam { template: String, param: String },
+
+    #[error("Template '{}' cannot send a template", .0)]
+    NestedTemplate(String),
+
+    #[error("Template '{}' does not result in a valid event", .template)]
+    InvalidTemplate {
+        template: String,
+        #[source]
+        source: serde_json::Error,
+    },
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -30,6 +93,87 @@ pub enum MqttError {
     #[error("Failed to subscribe")]
     Subscribing(#[source] rumqttc::v5::ClientError),
 
-    #[error("Connection failed")]
-    Connection(#[source] rumqttc::v5::ConnectionError),
+    #[error("Failed to set up TLS")]
+    Tls(#[source] TlsError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum QrCodeError {
+    #[error("No QR code fits into {max_modules}x{max_modules} modules{}", if *micro { "" } else { ", Micro QR codes are not allowed" })]
+    MatrixTooSmall { max_modules: u32, micro: bool },
+
+    #[error("{len} bytes of payload do not fit into a QR code of at most {max_modules}x{max_modules} modules")]
+    PayloadTooLarge { len: usize, max_modules: u32 },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SensorError {
+    #[error("Payload is not valid UTF-8")]
+    Utf8(#[source] std::str::Utf8Error),
+
+    #[error("Payload is not valid JSON")]
+    Json(#[source] serde_json::Error),
+
+    #[error("No value at JSON pointer '{}'", .0)]
+    MissingValue(String),
+
+    #[error("'{}' is not a number", .0)]
+    NotANumber(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CalendarError {
+    #[error("Failed to read calendar file from path '{}'", .path)]
+    ReadingFile {
+        path: camino::Utf8PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Failed to parse calendar")]
+    Parsing(#[source] ical::parser::ParserError),
+
+    #[error("Event is missing property {}", .0)]
+    MissingProperty(&'static str),
+
+    #[error("Invalid date or time '{}'", .0)]
+    InvalidDateTime(String),
+
+    #[error("Unsupported or invalid recurrence rule '{}'", .0)]
+    InvalidRecurrenceRule(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TlsError {
+    #[error("Failed to read certificates from path '{}'", .path)]
+    ReadingCertificates {
+        path: camino::Utf8PathBuf,
+        #[source]
+        source: rustls::pki_types::pem::Error,
+    },
+
+    #[error("Failed to read private key from path '{}'", .path)]
+    ReadingKey {
+        path: camino::Utf8PathBuf,
+        #[source]
+        source: rustls::pki_types::pem::Error,
+    },
+
+    #[error("No certificate authority found in '{}'", .0)]
+    NoCertificateAuthority(camino::Utf8PathBuf),
+
+    #[error("Invalid certificate authority")]
+    CertificateAuthority(#[source] rustls::Error),
+
+    #[error("Failed to set up certificate verification")]
+    Verifier(#[source] rustls::client::VerifierBuilderError),
+
+    #[error("Client certificate and key have to be configured together")]
+    IncompleteClientAuth,
+
+    #[error("Invalid client certificate or key")]
+    ClientAuth(#[source] rustls::Error),
+
+    #[error("Invalid server name '{}'", .0)]
+    InvalidServerName(String),
 }
@@ -1,10 +1,45 @@
-#[derive(Debug, serde::Deserialize)]
+/// An event in any version of the wire format, see [`crate::schema`]
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(try_from = "crate::schema::VersionedEvent")]
 #[cfg_attr(test, derive(serde::Serialize))]
 pub struct Event {
     pub event: EventInner,
+
+    /// Overlays with a higher priority preempt the one currently shown, overlays with the same
+    /// or a lower priority are queued
+    ///
+    /// `High` priority notifications are shown during quiet hours as well.
+    #[serde(default)]
+    #[cfg_attr(test, serde(skip_serializing_if = "Priority::is_normal"))]
+    pub priority: Priority,
+}
+
+impl Event {
+    pub fn new(event: EventInner) -> Self {
+        Self {
+            event,
+            priority: Priority::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Deserialize)]
+#[cfg_attr(test, derive(serde::Serialize))]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
 }
 
-#[derive(Debug, serde::Deserialize)]
+#[cfg(test)]
+impl Priority {
+    fn is_normal(&self) -> bool {
+        *self == Priority::Normal
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
 #[cfg_attr(test, derive(serde::Serialize))]
 pub enum EventInner {
     TurnOn,
@@ -12,7 +47,22 @@ pub enum EventInner {
 
     SetBrightness(u8),
 
+    /// Stop showing the current overlay or widget
+    ///
+    /// If `id` is set, only the overlay or widget with that `id` is dismissed, whether it is
+    /// shown or queued.
+    Dismiss {
+        id: Option<String>,
+    },
+
+    /// Drop all queued overlays, the one currently shown is kept
+    ClearQueue,
+
     ShowText {
+        /// Allows to dismiss or replace the text while it is shown or queued
+        #[serde(default)]
+        #[cfg_attr(test, serde(skip_serializing_if = "Option::is_none"))]
+        id: Option<String>,
         duration_secs: u32,
         text: String,
         r: u8,
@@ -23,6 +73,10 @@ pub enum EventInner {
     },
 
     ShowPreset {
+        /// Allows to dismiss or replace the preset while it is shown or queued
+        #[serde(default)]
+        #[cfg_attr(test, serde(skip_serializing_if = "Option::is_none"))]
+        id: Option<String>,
         name: String,
         duration_s: u64,
         c1: Option<u8>,
@@ -33,21 +87,309 @@ pub enum EventInner {
     },
 
     Json {
+        /// Allows to dismiss or replace the request while it is shown or queued
+        #[serde(default)]
+        #[cfg_attr(test, serde(skip_serializing_if = "Option::is_none"))]
+        id: Option<String>,
         value: serde_json::Value,
         sleep_s: u64,
     },
+
+    /// Show an effect rendered locally, unlike `ShowPreset` the clock keeps control of the
+    /// display
+    ShowEffect {
+        /// Allows to dismiss or replace the effect while it is shown or queued
+        #[serde(default)]
+        #[cfg_attr(test, serde(skip_serializing_if = "Option::is_none"))]
+        id: Option<String>,
+        effect: Effect,
+        duration_secs: u32,
+
+        /// The same seed always renders the same frames
+        #[serde(default)]
+        seed: u64,
+    },
+
+    /// Send `steps` one after another, `loops` times
+    ///
+    /// Overlays in the steps without an `id` get the `id` of the sequence, so that each step
+    /// replaces the one before, and dismissing the sequence also dismisses the step shown.
+    /// Sending another sequence with the same `id` stops the sequence playing.
+    Sequence {
+        #[serde(default)]
+        #[cfg_attr(test, serde(skip_serializing_if = "Option::is_none"))]
+        id: Option<String>,
+        steps: Vec<SequenceStep>,
+
+        #[serde(default = "default_loops")]
+        loops: u32,
+    },
+
+    /// Play a sequence from the `sequences` in the configuration, its `name` is its `id`
+    PlaySequence {
+        name: String,
+    },
+
+    /// Send the event of a template from `templates` in the configuration
+    Template {
+        name: String,
+
+        /// Values for `{param}` in the template
+        ///
+        /// A string in the template that consists of a single `{param}` is replaced by the value
+        /// as is, so that numbers can be passed as well.
+        #[serde(default)]
+        params: serde_json::Map<String, serde_json::Value>,
+    },
+
+    /// Show a progress bar
+    ///
+    /// Sending another `ShowProgress` with the same `id` while the bar is shown updates it in
+    /// place, without restarting the display duration.
+    ShowProgress {
+        id: String,
+        duration_secs: u32,
+
+        /// Progress in percent, clamped to 0..=100
+        percent: u8,
+        label: Option<String>,
+        r: u8,
+        g: u8,
+        b: u8,
+    },
+
+    /// Show a gauge for a value between `min` and `max`
+    ///
+    /// Sending another `ShowGauge` with the same `id` while the gauge is shown updates it in
+    /// place, without restarting the display duration.
+    ShowGauge {
+        id: String,
+        duration_secs: u32,
+        style: GaugeStyle,
+        min: f32,
+        max: f32,
+        value: f32,
+        label: Option<String>,
+
+        /// Default color, used if no threshold matches
+        r: u8,
+        g: u8,
+        b: u8,
+
+        #[serde(default)]
+        thresholds: Vec<ColorThreshold>,
+    },
+
+    /// Show a chart of a series of values, scaled to the matrix
+    ///
+    /// If there are more values than columns on the matrix, only the last values are shown.
+    /// Sending another `ShowChart` with the same `id` while the chart is shown updates it in
+    /// place, without restarting the display duration.
+    ShowChart {
+        id: String,
+        duration_secs: u32,
+        style: ChartStyle,
+        values: Vec<f32>,
+
+        /// Lower end of the scale, defaults to the smallest value
+        min: Option<f32>,
+
+        /// Upper end of the scale, defaults to the biggest value
+        max: Option<f32>,
+
+        /// Color of the lowest value
+        r: u8,
+        g: u8,
+        b: u8,
+
+        /// Color of the highest value, values in between get an interpolated color
+        ///
+        /// If not set, all values are drawn in the color of the lowest value.
+        gradient_to: Option<Color>,
+    },
+
+    /// Show `text` as QR code, using the full height of the matrix
+    ///
+    /// The smallest QR code version that fits onto the matrix with a border of one module above
+    /// and below is chosen. If `text` does not fit into any QR code that fits onto the matrix,
+    /// the event is refused.
+    ShowQrCode {
+        id: String,
+        duration_secs: u32,
+        text: String,
+
+        /// Allow Micro QR codes, which are smaller but cannot be scanned by most phone cameras
+        ///
+        /// If not set, Micro QR codes are used only if no regular QR code fits onto the matrix.
+        /// That is the case for the 32x16 matrix, which only fits Micro QR codes of up to 13
+        /// modules, holding 10 digits or 6 upper case letters.
+        #[serde(default)]
+        #[cfg_attr(test, serde(skip_serializing_if = "Option::is_none"))]
+        micro: Option<bool>,
+
+        /// Color of the light modules, dark modules are turned off
+        r: u8,
+        g: u8,
+        b: u8,
+    },
+}
+
+impl EventInner {
+    /// Whether the event shows something on the display, as opposed to controlling it
+    ///
+    /// A `Template` is not, as the event it results in is checked once it is expanded.
+    pub fn is_notification(&self) -> bool {
+        !std::matches!(
+            self,
+            EventInner::TurnOn
+                | EventInner::TurnOff
+                | EventInner::SetBrightness(_)
+                | EventInner::Dismiss { .. }
+                | EventInner::ClearQueue
+                | EventInner::Template { .. }
+        )
+    }
+
+    /// Whether the event takes over the display for a while, and thus has to be queued
+    pub fn is_overlay(&self) -> bool {
+        std::matches!(
+            self,
+            EventInner::ShowText { .. }
+                | EventInner::ShowPreset { .. }
+                | EventInner::Json { .. }
+                | EventInner::ShowEffect { .. }
+        )
+    }
+
+    /// The `id` of an overlay event
+    pub fn overlay_id(&self) -> Option<&str> {
+        match self {
+            EventInner::ShowText { id, .. }
+            | EventInner::ShowPreset { id, .. }
+            | EventInner::Json { id, .. }
+            | EventInner::ShowEffect { id, .. } => id.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn overlay_id_mut(&mut self) -> Option<&mut Option<String>> {
+        match self {
+            EventInner::ShowText { id, .. }
+            | EventInner::ShowPreset { id, .. }
+            | EventInner::Json { id, .. }
+            | EventInner::ShowEffect { id, .. } => Some(id),
+            _ => None,
+        }
+    }
+
+    /// How long an overlay event is shown
+    pub fn overlay_duration(&self) -> Option<std::time::Duration> {
+        let secs = match self {
+            EventInner::ShowText { duration_secs, .. }
+            | EventInner::ShowEffect { duration_secs, .. } => u64::from(*duration_secs),
+            EventInner::ShowPreset { duration_s, .. } => *duration_s,
+            EventInner::Json { sleep_s, .. } => *sleep_s,
+            _ => return None,
+        };
+        Some(std::time::Duration::from_secs(secs))
+    }
+
+    /// Change how long an overlay event is shown, rounded up to whole seconds
+    pub fn set_overlay_duration(&mut self, duration: std::time::Duration) {
+        let secs = duration.as_secs() + u64::from(duration.subsec_nanos() > 0);
+        match self {
+            EventInner::ShowText { duration_secs, .. }
+            | EventInner::ShowEffect { duration_secs, .. } => {
+                *duration_secs = u32::try_from(secs).unwrap_or(u32::MAX)
+            }
+            EventInner::ShowPreset { duration_s, .. } => *duration_s = secs,
+            EventInner::Json { sleep_s, .. } => *sleep_s = secs,
+            _ => {}
+        }
+    }
+
+    pub fn is_sequence(&self) -> bool {
+        std::matches!(
+            self,
+            EventInner::Sequence { .. } | EventInner::PlaySequence { .. }
+        )
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[cfg_attr(test, derive(serde::Serialize))]
+pub struct SequenceStep {
+    pub event: EventInner,
+
+    /// How long to wait before the next step
+    pub duration_secs: u32,
+}
+
+pub fn default_loops() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[cfg_attr(test, derive(serde::Serialize))]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl From<Color> for embedded_graphics::pixelcolor::Rgb888 {
+    fn from(value: Color) -> Self {
+        embedded_graphics::pixelcolor::Rgb888::new(value.r, value.g, value.b)
+    }
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[cfg_attr(test, derive(serde::Serialize))]
+pub enum ChartStyle {
+    Bars,
+    Line,
+}
+
+/// Effects rendered locally, see [`crate::effect`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[cfg_attr(test, derive(serde::Serialize))]
+pub enum Effect {
+    Plasma,
+    Fire,
+    MatrixRain,
+    Starfield,
+    GameOfLife,
+    Fireworks,
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[cfg_attr(test, derive(serde::Serialize))]
+pub enum GaugeStyle {
+    Horizontal,
+    Radial,
+}
+
+/// Color to use if a value is greater than or equal to `from`
+///
+/// If multiple thresholds match, the one with the highest `from` wins.
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[cfg_attr(test, derive(serde::Serialize))]
+pub struct ColorThreshold {
+    pub from: f32,
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
 }
 
 #[cfg(test)]
 mod tests {
     use crate::event::Event;
     use crate::event::EventInner;
+    use crate::event::Priority;
 
     #[test]
     fn test_turn_on() {
-        let e = Event {
-            event: EventInner::TurnOn,
-        };
+        let e = Event::new(EventInner::TurnOn);
         insta::assert_json_snapshot!(e, @r#"
         {
           "event": "TurnOn"
@@ -57,9 +399,7 @@ mod tests {
 
     #[test]
     fn test_turn_off() {
-        let e = Event {
-            event: EventInner::TurnOff,
-        };
+        let e = Event::new(EventInner::TurnOff);
         insta::assert_json_snapshot!(e, @r#"
         {
           "event": "TurnOff"
@@ -69,9 +409,7 @@ mod tests {
 
     #[test]
     fn test_set_brightness() {
-        let e = Event {
-            event: EventInner::SetBrightness(20),
-        };
+        let e = Event::new(EventInner::SetBrightness(20));
         insta::assert_json_snapshot!(e, @r#"
         {
           "event": {
@@ -83,30 +421,139 @@ mod tests {
 
     #[test]
     fn test_set_show_text() {
+        let e = Event::new(EventInner::ShowText {
+            id: None,
+            duration_secs: 10,
+            text: String::from("Hello"),
+            x: 1,
+            y: 1,
+            r: 100,
+            g: 100,
+            b: 100,
+        });
+        insta::assert_json_snapshot!(e, @r#"
+        {
+          "event": {
+            "ShowText": {
+              "duration_secs": 10,
+              "text": "Hello",
+              "r": 100,
+              "g": 100,
+              "b": 100,
+              "x": 1,
+              "y": 1
+            }
+          }
+        }
+        "#);
+    }
+
+    #[test]
+    fn test_show_effect() {
+        let e = Event::new(EventInner::ShowEffect {
+            id: None,
+            effect: crate::event::Effect::Plasma,
+            duration_secs: 20,
+            seed: 7,
+        });
+        insta::assert_json_snapshot!(e, @r#"
+        {
+          "event": {
+            "ShowEffect": {
+              "effect": "Plasma",
+              "duration_secs": 20,
+              "seed": 7
+            }
+          }
+        }
+        "#);
+    }
+
+    #[test]
+    fn test_sequence() {
+        let e = Event::new(EventInner::Sequence {
+            id: Some(String::from("doorbell")),
+            steps: vec![crate::event::SequenceStep {
+                event: EventInner::TurnOn,
+                duration_secs: 5,
+            }],
+            loops: 1,
+        });
+        insta::assert_json_snapshot!(e, @r#"
+        {
+          "event": {
+            "Sequence": {
+              "id": "doorbell",
+              "steps": [
+                {
+                  "event": "TurnOn",
+                  "duration_secs": 5
+                }
+              ],
+              "loops": 1
+            }
+          }
+        }
+        "#);
+    }
+
+    #[test]
+    fn test_show_progress() {
+        let e = Event::new(EventInner::ShowProgress {
+            id: String::from("dishwasher"),
+            duration_secs: 60,
+            percent: 42,
+            label: Some(String::from("Dish")),
+            r: 0,
+            g: 100,
+            b: 0,
+        });
+        insta::assert_json_snapshot!(e, @r#"
+        {
+          "event": {
+            "ShowProgress": {
+              "id": "dishwasher",
+              "duration_secs": 60,
+              "percent": 42,
+              "label": "Dish",
+              "r": 0,
+              "g": 100,
+              "b": 0
+            }
+          }
+        }
+        "#);
+    }
+
+    #[test]
+    fn test_high_priority() {
         let e = Event {
             event: EventInner::ShowText {
+                id: None,
                 duration_secs: 10,
-                text: String::from("Hello"),
+                text: String::from("Door"),
                 x: 1,
                 y: 1,
-                r: 100,
-                g: 100,
-                b: 100,
+                r: 255,
+                g: 0,
+                b: 0,
             },
+            priority: Priority::High,
         };
         insta::assert_json_snapshot!(e, @r#"
         {
           "event": {
             "ShowText": {
               "duration_secs": 10,
-              "text": "Hello",
-              "r": 100,
-              "g": 100,
-              "b": 100,
+              "text": "Door",
+              "r": 255,
+              "g": 0,
+              "b": 0,
               "x": 1,
               "y": 1
             }
-          }
+          },
+          "priority": "High"
         }
         "#);
     }
@@ -129,6 +576,72 @@ mod tests {
         let _: Event = serde_json::from_str(s).unwrap();
     }
 
+    #[test]
+    fn test_deser_testfile_show_progress() {
+        let s = include_str!("../test/show_progress.json");
+        let _: Event = serde_json::from_str(s).unwrap();
+    }
+
+    #[test]
+    fn test_deser_testfile_show_gauge() {
+        let s = include_str!("../test/show_gauge.json");
+        let _: Event = serde_json::from_str(s).unwrap();
+    }
+
+    #[test]
+    fn test_deser_testfile_show_chart() {
+        let s = include_str!("../test/show_chart.json");
+        let _: Event = serde_json::from_str(s).unwrap();
+    }
+
+    #[test]
+    fn test_deser_testfile_show_qr_code() {
+        let s = include_str!("../test/show_qr_code.json");
+        let _: Event = serde_json::from_str(s).unwrap();
+    }
+
+    #[test]
+    fn test_deser_testfile_show_effect() {
+        let s = include_str!("../test/show_effect.json");
+        let _: Event = serde_json::from_str(s).unwrap();
+    }
+
+    #[test]
+    fn test_deser_testfile_sequence() {
+        let s = include_str!("../test/sequence.json");
+        let _: Event = serde_json::from_str(s).unwrap();
+    }
+
+    #[test]
+    fn test_deser_testfile_play_sequence() {
+        let s = include_str!("../test/play_sequence.json");
+        let _: Event = serde_json::from_str(s).unwrap();
+    }
+
+    #[test]
+    fn test_deser_testfile_template() {
+        let s = include_str!("../test/template.json");
+        let _: Event = serde_json::from_str(s).unwrap();
+    }
+
+    #[test]
+    fn test_deser_testfile_v2_show_text() {
+        let s = include_str!("../test/v2_show_text.json");
+        let _: Event = serde_json::from_str(s).unwrap();
+    }
+
+    #[test]
+    fn test_deser_testfile_dismiss() {
+        let s = include_str!("../test/dismiss.json");
+        let _: Event = serde_json::from_str(s).unwrap();
+    }
+
+    #[test]
+    fn test_deser_testfile_clear_queue() {
+        let s = include_str!("../test/clear_queue.json");
+        let _: Event = serde_json::from_str(s).unwrap();
+    }
+
     #[test]
     fn test_deser_testfile_turn_off() {
         let s = include_str!("../test/turn_off.json");
@@ -0,0 +1,225 @@
+use serde_json::json;
+use serde_json::Value;
+use url::Url;
+
+use crate::config::HomeAssistantConfig;
+
+/// Home Assistant template of the value sent by an entity, as JSON
+const VALUE: &str = "{{ value | tojson }}";
+
+/// Names of the effects WLED offers
+pub async fn effects(
+    wled_client: &reqwest::Client,
+    effects_url: Url,
+) -> Result<Vec<String>, crate::error::Error> {
+    wled_client
+        .get(effects_url)
+        .send()
+        .await
+        .map_err(crate::error::Error::Reqwest)?
+        .json::<Vec<String>>()
+        .await
+        .map_err(crate::error::Error::Reqwest)
+}
+
+/// Discovery configs of the entities of the clock, with the topics they are published to
+///
+/// The clock is a light, texts sent via the notification entity are shown with `ShowText` and
+/// the effect entity shows WLED effects with `ShowPreset`. It is only offered if `effects` are
+/// known.
+pub fn discovery_configs(
+    config: &HomeAssistantConfig,
+    topic_prefix: &str,
+    effects: Option<&[String]>,
+) -> Vec<(String, Value)> {
+    let events_topic = format!("{topic_prefix}/events");
+    let state_topic = format!("{topic_prefix}/state");
+    let availability_topic = format!("{topic_prefix}/availability");
+    let node_id = config.node_id.as_str();
+    let topic = |component: &str, object_id: &str| {
+        format!(
+            "{prefix}/{component}/{node_id}/{object_id}/config",
+            prefix = config.discovery_prefix
+        )
+    };
+
+    let device = json!({
+        "identifiers": [node_id],
+        "name": node_id,
+        "model": env!("CARGO_PKG_NAME"),
+        "sw_version": env!("CARGO_PKG_VERSION"),
+    });
+    let availability = json!([{ "topic": availability_topic }]);
+
+    let turn_on = json!({ "event": "TurnOn" }).to_string();
+    let turn_off = json!({ "event": "TurnOff" }).to_string();
+    let light = json!({
+        "name": null,
+        "unique_id": format!("{node_id}_light"),
+        "device": device,
+        "availability": availability,
+        "command_topic": events_topic,
+        "payload_on": turn_on,
+        "payload_off": turn_off,
+        // `TurnOn` resets the brightness, so it has to be sent before the brightness
+        "on_command_type": "first",
+        "state_topic": state_topic,
+        "state_value_template": format!("{{{{ '{turn_on}' if value_json.on else '{turn_off}' }}}}"),
+        "brightness_command_topic": events_topic,
+        "brightness_command_template": command_template(json!({
+            "event": { "SetBrightness": VALUE },
+        })),
+        "brightness_scale": 100,
+        "brightness_state_topic": state_topic,
+        "brightness_value_template": "{{ value_json.brightness }}",
+    });
+
+    let color = config.notification_color;
+    let notification = json!({
+        "name": "Notification",
+        "unique_id": format!("{node_id}_notification"),
+        "icon": "mdi:message-text",
+        "device": { "identifiers": [node_id] },
+        "availability": availability,
+        "command_topic": events_topic,
+        "command_template": command_template(json!({
+            "event": {
+                "ShowText": {
+                    "duration_secs": config.notification_duration.as_secs(),
+                    "text": VALUE,
+                    "r": color.r,
+                    "g": color.g,
+                    "b": color.b,
+                    "x": config.notification_x,
+                    "y": config.notification_y,
+                }
+            },
+        })),
+    });
+
+    let mut configs = vec![
+        (topic("light", "light"), light),
+        (topic("text", "notification"), notification),
+    ];
+
+    if let Some(effects) = effects {
+        let effect = json!({
+            "name": "Effect",
+            "unique_id": format!("{node_id}_effect"),
+            "icon": "mdi:auto-fix",
+            "device": { "identifiers": [node_id] },
+            "availability": availability,
+            "command_topic": events_topic,
+            "command_template": command_template(json!({
+                "event": {
+                    "ShowPreset": {
+                        "id": "home-assistant-effect",
+                        "name": VALUE,
+                        "duration_s": config.effect_duration.as_secs(),
+                    }
+                },
+            })),
+            "options": effects,
+        });
+        configs.push((topic("select", "effect"), effect));
+    }
+
+    configs
+}
+
+/// `event` as text, with the [`VALUE`] placeholder as template instead of a string
+fn command_template(event: Value) -> String {
+    event.to_string().replace(&format!("\"{VALUE}\""), VALUE)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::HomeAssistantConfig;
+
+    #[test]
+    fn test_discovery_configs() {
+        let config: HomeAssistantConfig = toml::from_str(r#"node_id = "clock""#).unwrap();
+        let effects = [String::from("Solid"), String::from("Blink")];
+
+        let configs = super::discovery_configs(&config, "clock", Some(&effects));
+        insta::assert_json_snapshot!(configs, @r#"
+        [
+          [
+            "homeassistant/light/clock/light/config",
+            {
+              "availability": [
+                {
+                  "topic": "clock/availability"
+                }
+              ],
+              "brightness_command_template": "{\"event\":{\"SetBrightness\":{{ value | tojson }}}}",
+              "brightness_command_topic": "clock/events",
+              "brightness_scale": 100,
+              "brightness_state_topic": "clock/state",
+              "brightness_value_template": "{{ value_json.brightness }}",
+              "command_topic": "clock/events",
+              "device": {
+                "identifiers": [
+                  "clock"
+                ],
+                "model": "clock",
+                "name": "clock",
+                "sw_version": "0.1.0"
+              },
+              "name": null,
+              "on_command_type": "first",
+              "payload_off": "{\"event\":\"TurnOff\"}",
+              "payload_on": "{\"event\":\"TurnOn\"}",
+              "state_topic": "clock/state",
+              "state_value_template": "{{ '{\"event\":\"TurnOn\"}' if value_json.on else '{\"event\":\"TurnOff\"}' }}",
+              "unique_id": "clock_light"
+            }
+          ],
+          [
+            "homeassistant/text/clock/notification/config",
+            {
+              "availability": [
+                {
+                  "topic": "clock/availability"
+                }
+              ],
+              "command_template": "{\"event\":{\"ShowText\":{\"b\":255,\"duration_secs\":10,\"g\":255,\"r\":255,\"text\":{{ value | tojson }},\"x\":1,\"y\":10}}}",
+              "command_topic": "clock/events",
+              "device": {
+                "identifiers": [
+                  "clock"
+                ]
+              },
+              "icon": "mdi:message-text",
+              "name": "Notification",
+              "unique_id": "clock_notification"
+            }
+          ],
+          [
+            "homeassistant/select/clock/effect/config",
+            {
+              "availability": [
+                {
+                  "topic": "clock/availability"
+                }
+              ],
+              "command_template": "{\"event\":{\"ShowPreset\":{\"duration_s\":60,\"id\":\"home-assistant-effect\",\"name\":{{ value | tojson }}}}}",
+              "command_topic": "clock/events",
+              "device": {
+                "identifiers": [
+                  "clock"
+                ]
+              },
+              "icon": "mdi:auto-fix",
+              "name": "Effect",
+              "options": [
+                "Solid",
+                "Blink"
+              ],
+              "unique_id": "clock_effect"
+            }
+          ]
+        ]
+        "#);
+    }
+}
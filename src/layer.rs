@@ -0,0 +1,295 @@
+use std::sync::Arc;
+
+use embedded_graphics::geometry::Dimensions;
+use embedded_graphics::geometry::OriginDimensions;
+use embedded_graphics::geometry::Point;
+use embedded_graphics::geometry::Size;
+use embedded_graphics::pixelcolor::Rgb888;
+use embedded_graphics::prelude::DrawTarget;
+use embedded_graphics::prelude::RgbColor;
+use embedded_graphics::primitives::PointsIter;
+use embedded_graphics::Pixel;
+use rgb::RGB8;
+use smart_leds_matrix::layout::Layout;
+use smart_leds_matrix::SmartLedMatrix;
+use smart_leds_trait::SmartLedsWrite;
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::LayerOpacityConfig;
+use crate::state::ClockRunning;
+
+/// A frame buffer in which pixels that were not drawn are transparent
+#[derive(Debug, Clone)]
+pub struct FrameBuffer {
+    size: Size,
+    pixels: Vec<Option<Rgb888>>,
+}
+
+impl FrameBuffer {
+    pub fn new(size: Size) -> Self {
+        Self {
+            size,
+            pixels: vec![None; (size.width * size.height) as usize],
+        }
+    }
+
+    /// Make every pixel transparent
+    pub fn clear_transparent(&mut self) {
+        self.pixels.fill(None);
+    }
+
+    /// The color at `point`, `None` if it is transparent or out of bounds
+    pub fn pixel(&self, point: Point) -> Option<Rgb888> {
+        self.index(point).and_then(|idx| self.pixels[idx])
+    }
+
+    fn index(&self, point: Point) -> Option<usize> {
+        let x = u32::try_from(point.x)
+            .ok()
+            .filter(|x| *x < self.size.width)?;
+        let y = u32::try_from(point.y)
+            .ok()
+            .filter(|y| *y < self.size.height)?;
+        Some((y * self.size.width + x) as usize)
+    }
+}
+
+impl OriginDimensions for FrameBuffer {
+    fn size(&self) -> Size {
+        self.size
+    }
+}
+
+impl DrawTarget for FrameBuffer {
+    type Color = Rgb888;
+    type Error = std::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if let Some(idx) = self.index(point) {
+                self.pixels[idx] = Some(color);
+            }
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.pixels.fill(Some(color));
+        Ok(())
+    }
+}
+
+/// The layers the display is composed of, from bottom to top
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+    /// An effect behind the clock
+    Background,
+    Clock,
+    Widgets,
+    Notifications,
+}
+
+impl Layer {
+    const ALL: [Layer; 4] = [
+        Layer::Background,
+        Layer::Clock,
+        Layer::Widgets,
+        Layer::Notifications,
+    ];
+}
+
+/// A frame buffer per [`Layer`], blended with their opacity into the frame shown
+#[derive(Debug)]
+pub struct Layers {
+    buffers: [FrameBuffer; 4],
+
+    /// In percent
+    opacity: [u8; 4],
+}
+
+impl Layers {
+    pub fn new(size: Size, config: &LayerOpacityConfig) -> Self {
+        Self {
+            buffers: std::array::from_fn(|_| FrameBuffer::new(size)),
+            opacity: [
+                config.background,
+                config.clock,
+                config.widgets,
+                config.notifications,
+            ]
+            .map(|opacity| opacity.min(100)),
+        }
+    }
+
+    pub fn size(&self) -> Size {
+        self.buffers[0].size()
+    }
+
+    pub fn get_mut(&mut self, layer: Layer) -> &mut FrameBuffer {
+        &mut self.buffers[layer as usize]
+    }
+
+    /// The blended frame, pixels that are transparent in every layer are black
+    pub fn compose(&self) -> impl Iterator<Item = Pixel<Rgb888>> + '_ {
+        self.buffers[0].bounding_box().points().map(|point| {
+            let color = Layer::ALL.iter().fold(Rgb888::BLACK, |below, layer| {
+                match self.buffers[*layer as usize].pixel(point) {
+                    Some(above) => blend(below, above, self.opacity[*layer as usize]),
+                    None => below,
+                }
+            });
+            Pixel(point, color)
+        })
+    }
+}
+
+/// `above` drawn over `below` with `opacity` in percent
+fn blend(below: Rgb888, above: Rgb888, opacity: u8) -> Rgb888 {
+    let opacity = u16::from(opacity);
+    let channel = |below: u8, above: u8| {
+        ((below as u16 * (100 - opacity) + above as u16 * opacity) / 100) as u8
+    };
+
+    Rgb888::new(
+        channel(below.r(), above.r()),
+        channel(below.g(), above.g()),
+        channel(below.b(), above.b()),
+    )
+}
+
+/// Sends the composed layers to the matrix
+pub struct RenderTask<T, L, const SIZE: usize>
+where
+    T: SmartLedsWrite,
+    L: Layout,
+    <T as SmartLedsWrite>::Color: From<RGB8>,
+{
+    interval: std::time::Duration,
+    running: Arc<ClockRunning>,
+    cancellation_token: CancellationToken,
+    matrix: Arc<Mutex<SmartLedMatrix<T, L, SIZE>>>,
+    layers: Arc<Mutex<Layers>>,
+}
+
+impl<T, L, const SIZE: usize> RenderTask<T, L, SIZE>
+where
+    T: SmartLedsWrite,
+    L: Layout,
+    <T as SmartLedsWrite>::Color: From<RGB8>,
+    <T as SmartLedsWrite>::Error: std::fmt::Debug,
+{
+    pub fn new(
+        interval: std::time::Duration,
+        running: Arc<ClockRunning>,
+        cancellation_token: CancellationToken,
+        matrix: Arc<Mutex<SmartLedMatrix<T, L, SIZE>>>,
+        layers: Arc<Mutex<Layers>>,
+    ) -> Self {
+        Self {
+            interval,
+            running,
+            cancellation_token,
+            matrix,
+            layers,
+        }
+    }
+
+    pub async fn run(self) -> Result<(), crate::error::Error> {
+        let mut render_interval = tokio::time::interval(self.interval);
+
+        loop {
+            let Some(_tick) = self
+                .cancellation_token
+                .run_until_cancelled(render_interval.tick())
+                .await
+            else {
+                tracing::info!("Ending rendering");
+                break;
+            };
+
+            // Paused while WLED shows something itself, so that the DDP stream does not hide it
+            if !self.running.is_running() {
+                continue;
+            }
+
+            let layers = self.layers.lock().await;
+            let mut matrix = self.matrix.lock().await;
+            matrix.draw_iter(layers.compose()).unwrap();
+            if let Err(error) = matrix.flush() {
+                tracing::error!(?error, "Failed to send frame, retrying with the next one");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_graphics::geometry::Point;
+    use embedded_graphics::geometry::Size;
+    use embedded_graphics::pixelcolor::Rgb888;
+    use embedded_graphics::prelude::DrawTarget;
+    use embedded_graphics::Pixel;
+
+    use super::Layer;
+    use super::Layers;
+    use crate::config::LayerOpacityConfig;
+
+    fn composed(layers: &Layers) -> Vec<Rgb888> {
+        layers.compose().map(|Pixel(_, color)| color).collect()
+    }
+
+    fn draw(layers: &mut Layers, layer: Layer, x: i32, color: Rgb888) {
+        layers
+            .get_mut(layer)
+            .draw_iter([Pixel(Point::new(x, 0), color)])
+            .unwrap();
+    }
+
+    #[test]
+    fn test_transparent_pixels_show_layers_below() {
+        let mut layers = Layers::new(Size::new(3, 1), &LayerOpacityConfig::default());
+        layers
+            .get_mut(Layer::Background)
+            .clear(Rgb888::new(0, 0, 100))
+            .unwrap();
+        draw(&mut layers, Layer::Clock, 1, Rgb888::new(200, 0, 0));
+        draw(&mut layers, Layer::Notifications, 2, Rgb888::new(0, 50, 0));
+
+        assert_eq!(
+            composed(&layers),
+            [
+                Rgb888::new(0, 0, 100),
+                Rgb888::new(200, 0, 0),
+                Rgb888::new(0, 50, 0)
+            ]
+        );
+
+        layers.get_mut(Layer::Background).clear_transparent();
+        assert_eq!(composed(&layers)[0], Rgb888::new(0, 0, 0));
+    }
+
+    #[test]
+    fn test_opacity() {
+        let config = LayerOpacityConfig {
+            notifications: 50,
+            ..Default::default()
+        };
+        let mut layers = Layers::new(Size::new(2, 1), &config);
+        layers
+            .get_mut(Layer::Clock)
+            .clear(Rgb888::new(200, 100, 0))
+            .unwrap();
+        draw(&mut layers, Layer::Notifications, 1, Rgb888::new(0, 0, 250));
+
+        assert_eq!(
+            composed(&layers),
+            [Rgb888::new(200, 100, 0), Rgb888::new(100, 50, 125)]
+        );
+    }
+}
@@ -1,41 +1,70 @@
 use std::sync::Arc;
 use std::time::Instant;
 
-use embedded_graphics::geometry::Point;
 use embedded_graphics::prelude::DrawTarget;
-use embedded_graphics::text::Text;
-use embedded_graphics::Drawable;
 use smart_leds_matrix::layout::Rectangular;
 use smart_leds_matrix::SmartLedMatrix;
 use tokio::sync::Mutex;
 use url::Url;
 
+mod ambient;
+mod astro;
+mod brightness;
+mod calendar;
+mod carousel;
 mod cli;
 mod clock_task;
+mod compositor;
 mod config;
+mod effect;
 mod error;
 mod event;
+mod home_assistant;
+mod layer;
 mod logging;
 mod mqtt;
+mod notification;
+mod overlay;
+mod power;
+mod scheduler;
+mod schema;
+mod sensor;
+mod sequence;
+mod state;
 mod systemd;
+mod template;
+mod tls;
 mod util;
+mod widget;
 mod writer;
 
+const MATRIX_WIDTH: u32 = 32;
+const MATRIX_HEIGHT: u32 = 16;
+
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> color_eyre::eyre::Result<()> {
     setup_panic();
     color_eyre::install().map_err(crate::error::Error::InstallingColorEyre)?;
     let cli = <crate::cli::Cli as clap::Parser>::parse();
     crate::logging::setup(cli.verbosity);
-    let cfg = crate::config::Config::load(&cli.config).await?;
 
     match cli.command {
         cli::Command::Run => {
+            let cfg = crate::config::Config::load(&cli.config).await?;
             run(cli, cfg).await?;
         }
         cli::Command::VerifyConfig => {
+            crate::config::Config::load(&cli.config).await?;
             tracing::info!("Configuration verified");
         }
+        cli::Command::ListSchedules { count } => {
+            let cfg = crate::config::Config::load(&cli.config).await?;
+            scheduler::list(&cfg.schedules, count)?;
+        }
+        cli::Command::EventSchema { schema_version } => {
+            let schema = schema::event_schema(schema_version);
+            println!("{}", serde_json::to_string_pretty(&schema).unwrap());
+        }
     }
 
     Ok(())
@@ -53,12 +82,14 @@ async fn run(
     _cli: crate::cli::Cli,
     config: crate::config::Config,
 ) -> Result<(), crate::error::Error> {
-    let ddp_connection = ddp_rs::connection::DDPConnection::try_new(
-        format!("{}:{}", config.display.host, config.display.port),
-        ddp_rs::protocol::PixelConfig::default(), // Default is RGB, 8 bits ber channel
-        ddp_rs::protocol::ID::Default,
-        std::net::UdpSocket::bind(format!("0.0.0.0:{}", config.display.udp_port))
-            .map_err(crate::error::Error::UDPBind)?,
+    // Resolved again by the resolver task started below
+    let display_address = writer::resolve(&config.display.host, config.display.port).await?;
+    let (display_address_sender, display_address) = tokio::sync::watch::channel(display_address);
+    let resolve_display = Arc::new(tokio::sync::Notify::new());
+    let writer = writer::Writer::connect(
+        display_address,
+        resolve_display.clone(),
+        config.display.udp_port,
     )?;
     tracing::info!("Created DDP connection");
 
@@ -90,8 +121,10 @@ async fn run(
         "Booted WLED clock successfully"
     );
 
-    let writer = writer::Writer::new(ddp_connection);
-    let mut matrix = SmartLedMatrix::<_, _, { 32 * 16 }>::new(writer, Rectangular::new(32, 16));
+    let mut matrix = SmartLedMatrix::<_, _, { (MATRIX_WIDTH * MATRIX_HEIGHT) as usize }>::new(
+        writer,
+        Rectangular::new(MATRIX_WIDTH, MATRIX_HEIGHT),
+    );
     matrix.set_brightness(config.display.initial_brightness.clamp(0, 100));
     matrix
         .clear(embedded_graphics::pixelcolor::Rgb888::default())
@@ -99,33 +132,213 @@ async fn run(
     matrix.flush()?;
 
     let (event_sender, mut event_receiver) = tokio::sync::mpsc::channel::<event::Event>(100);
+    let (refusal_sender, refusal_receiver) = tokio::sync::mpsc::channel::<mqtt::Refusal>(100);
     let cancellation_token = tokio_util::sync::CancellationToken::new();
     let matrix = Arc::new(Mutex::new(matrix));
-    let clock_displaying_is_running = Arc::new(std::sync::atomic::AtomicBool::from(true));
+    let layers = Arc::new(Mutex::new(layer::Layers::new(
+        embedded_graphics::geometry::Size::new(MATRIX_WIDTH, MATRIX_HEIGHT),
+        &config.display.layer_opacity,
+    )));
+    let clock_running = Arc::new(state::ClockRunning::new(
+        config.display.bootstate.into_bool(),
+    ));
+    let widgets = Arc::new(Mutex::new(widget::WidgetSlot::default()));
+    let sensor_values = Arc::new(Mutex::new(sensor::SensorValues::new(
+        config.mqtt.sensors.clone(),
+    )));
+    let next_appointment = Arc::new(Mutex::new(None));
+    let manual_brightness_set_at = Arc::new(Mutex::new(None));
+    let quiet_hours = Arc::new(Mutex::new(power::QuietHours::default()));
+    let clock_state = state::SharedState::new(state::ClockState {
+        on: config.display.bootstate.into_bool(),
+        brightness: config.display.initial_brightness.clamp(0, 100),
+        mode: state::Mode::Clock,
+        notification_id: None,
+        queue_length: 0,
+    });
+    let ambient_light = config
+        .display
+        .auto_brightness
+        .as_ref()
+        .map(|auto_brightness_config| {
+            Arc::new(Mutex::new(ambient::AmbientLight::new(
+                auto_brightness_config,
+            )))
+        });
+
+    if config.display.brightness_schedule.is_some() && config.display.auto_brightness.is_some() {
+        return Err(crate::error::Error::ConflictingBrightnessModes);
+    }
+    let templates = template::Templates::new(config.templates.clone())?;
+    sequence::validate_config(&config.sequences, &templates)?;
+
+    let discovery_configs = match config.mqtt.home_assistant.as_ref() {
+        Some(home_assistant) => {
+            let effects = home_assistant::effects(&wled_client, effects_url.clone())
+                .await
+                .inspect_err(|error| {
+                    tracing::error!(
+                        ?error,
+                        "Failed to get effects, not offering them to Home Assistant"
+                    )
+                })
+                .ok();
+            home_assistant::discovery_configs(
+                home_assistant,
+                &config.mqtt.topic_prefix,
+                effects.as_deref(),
+            )
+        }
+        None => Vec::new(),
+    };
 
-    tokio::task::spawn({
+    let mqtt_task = tokio::task::spawn({
         let mqtt_config = config.mqtt.clone();
         let cancellation_token = cancellation_token.clone();
         mqtt::run(
             mqtt_config,
             cancellation_token.clone(),
             event_sender.clone(),
+            sensor_values.clone(),
+            ambient_light.clone(),
+            clock_state.subscribe(),
+            discovery_configs,
+            refusal_receiver,
         )
     });
     tokio::task::spawn({
-        clock_task::ClockTask::new(
-            clock_displaying_is_running.clone(),
+        writer::ResolverTask::new(
+            config.display.host.clone(),
+            config.display.port,
+            display_address_sender,
+            resolve_display,
+            cancellation_token.clone(),
+        )
+        .run()
+    });
+    tokio::task::spawn({
+        layer::RenderTask::new(
+            config.display.frame_interval(),
+            clock_running.clone(),
             cancellation_token.clone(),
             matrix.clone(),
-            &config,
+            layers.clone(),
         )
         .run()
     });
+    if let Some(background) = config.display.background {
+        tokio::task::spawn({
+            effect::BackgroundTask::new(
+                effect::EffectRenderer::new(
+                    background.effect,
+                    background.seed,
+                    embedded_graphics::geometry::Size::new(MATRIX_WIDTH, MATRIX_HEIGHT),
+                ),
+                config.display.frame_interval(),
+                clock_running.clone(),
+                cancellation_token.clone(),
+                layers.clone(),
+            )
+            .run()
+        });
+    }
+    tokio::task::spawn({
+        clock_task::ClockTask::new(
+            clock_running.clone(),
+            cancellation_token.clone(),
+            layers.clone(),
+            widgets.clone(),
+            sensor_values.clone(),
+            next_appointment.clone(),
+            &config,
+        )?
+        .run()
+    });
+    if let Some(schedule_config) = config.display.brightness_schedule.clone() {
+        tokio::task::spawn({
+            brightness::BrightnessTask::new(
+                brightness::BrightnessSchedule::new(schedule_config, config.location)?,
+                cancellation_token.clone(),
+                matrix.clone(),
+                manual_brightness_set_at.clone(),
+                clock_state.clone(),
+            )
+            .run()
+        });
+    }
+    if let (Some(auto_brightness_config), Some(ambient_light)) =
+        (config.display.auto_brightness.clone(), ambient_light)
+    {
+        tokio::task::spawn({
+            ambient::AutoBrightnessTask::new(
+                ambient::AutoBrightness::new(auto_brightness_config)?,
+                ambient_light,
+                cancellation_token.clone(),
+                matrix.clone(),
+                manual_brightness_set_at.clone(),
+                clock_state.clone(),
+            )
+            .run()
+        });
+    }
+    if !config.display.power_schedule.is_empty() || config.display.quiet_hours.is_some() {
+        tokio::task::spawn({
+            power::PowerTask::new(
+                &config,
+                cancellation_token.clone(),
+                event_sender.clone(),
+                quiet_hours.clone(),
+            )?
+            .run()
+        });
+    }
+    if !config.schedules.is_empty() {
+        tokio::task::spawn({
+            scheduler::SchedulerTask::new(
+                scheduler::load(&config.schedules)?,
+                cancellation_token.clone(),
+                event_sender.clone(),
+            )
+            .run()
+        });
+    }
+    if let Some(calendar_config) = config.calendar.clone() {
+        tokio::task::spawn({
+            calendar::CalendarTask::new(
+                calendar_config,
+                cancellation_token.clone(),
+                next_appointment.clone(),
+            )
+            .run()
+        });
+    }
 
     // Track the brightness that was set via the MQTT API, so we can re-use it when turning on
     let mut set_brightness = None;
     let mut last_turn_on: Option<Instant> = None;
 
+    let mut sequencer = sequence::Sequencer::new(cancellation_token.clone(), event_sender.clone());
+
+    // Overlays are shown by the compositor, so that they do not block the handling of other events
+    let (compositor_sender, compositor_receiver) = tokio::sync::mpsc::channel(100);
+    tokio::task::spawn({
+        compositor::CompositorTask::new(
+            overlay::OverlayPlayer::new(
+                wled_client.clone(),
+                state_url.clone(),
+                effects_url.clone(),
+                layers.clone(),
+                clock_running.clone(),
+                &config.display,
+            ),
+            widgets.clone(),
+            clock_state.clone(),
+            compositor_receiver,
+            cancellation_token.clone(),
+        )
+        .run()
+    });
+
     loop {
         tokio::select! {
             _ctrl_c = tokio::signal::ctrl_c() => {
@@ -137,6 +350,32 @@ async fn run(
             event = event_receiver.recv() => {
                 let Some(event) = event else { tracing::error!("Receiver closed"); break };
 
+                // Expanded in place, sending the expanded event to the channel could wait forever
+                // for this loop to receive from it
+                let event = match event.event {
+                    event::EventInner::Template { name, params } => {
+                        match templates.expand(&name, &params, event.priority) {
+                            Ok(event) => {
+                                tracing::info!(?name, ?event, "Expanded template");
+                                event
+                            }
+                            Err(error) => {
+                                tracing::error!(?error, "Refusing to expand template: {error}");
+                                continue
+                            }
+                        }
+                    }
+                    _ => event,
+                };
+                let Some(event) = quiet_hours.lock().await.admit(event) else { continue };
+
+                if event.event.is_overlay() {
+                    if let Err(error) = compositor_sender.send(compositor::Command::Show(event)).await {
+                        tracing::error!(?error, "Failed to send overlay to compositor");
+                    }
+                    continue
+                }
+
                 match event.event {
                     event::EventInner::TurnOn => {
                         if let Some(last_turn_on) = last_turn_on {
@@ -147,7 +386,7 @@ async fn run(
                         }
 
                         // Turn on the clock display task
-                        clock_displaying_is_running.store(true, std::sync::atomic::Ordering::Relaxed);
+                        clock_running.set_on(true);
 
                         if let Err(error) = wled_client
                             .post(state_url.clone())
@@ -167,7 +406,7 @@ async fn run(
                             }
 
                             // Timeout, retry
-                            if let Err(error) = event_sender.send(event::Event { event: event::EventInner::TurnOn }).await {
+                            if let Err(error) = event_sender.send(event::Event::new(event::EventInner::TurnOn)).await {
                                 tracing::error!(?error, "Failed to send event to channel");
                             }
                             continue;
@@ -183,6 +422,10 @@ async fn run(
                                 .unwrap();
                             matrix.flush()?;
                         }
+                        clock_state.update(|state| {
+                            state.on = true;
+                            state.brightness = config.display.initial_brightness.clamp(0, 100);
+                        });
                         last_turn_on = Some(Instant::now());
                     },
 
@@ -203,7 +446,7 @@ async fn run(
                             }
 
                             // Timeout, retry
-                            if let Err(error) = event_sender.send(event::Event { event: event::EventInner::TurnOff }).await {
+                            if let Err(error) = event_sender.send(event::Event::new(event::EventInner::TurnOff)).await {
                                 tracing::error!(?error, "Failed to send event to channel");
                             }
                             continue;
@@ -212,130 +455,136 @@ async fn run(
                         tracing::info!("Updated WLED state");
 
                         // Turn off the clock display task
-                        clock_displaying_is_running.store(false, std::sync::atomic::Ordering::Relaxed);
+                        clock_running.set_on(false);
+                        clock_state.update(|state| state.on = false);
                     },
 
                     event::EventInner::SetBrightness(brightness) => {
                         set_brightness = Some(brightness);
                         tracing::info!(?brightness, "Setting brightness");
-                        let mut matrix = matrix.lock().await;
-                        matrix.set_brightness(brightness.clamp(5, 100));
-                    },
-
-                    event::EventInner::ShowText { duration_secs, text, r,g,b, x, y } => {
-                        tracing::info!(?duration_secs, ?text, "Showing text");
 
-                        let mut render_interval = tokio::time::interval(config.display.interval);
-                        let start_time = std::time::Instant::now();
-                        let duration_secs = std::time::Duration::from_secs(duration_secs.into());
+                        // Overrides the brightness schedule for a while
+                        match time::OffsetDateTime::now_local() {
+                            Ok(now) => *manual_brightness_set_at.lock().await = Some(now),
+                            Err(error) => tracing::error!(?error, "Failed to get local time"),
+                        }
 
-                        let offset = Point::new(
-                            x.into(),
-                            y.into(),
-                        );
+                        let brightness = brightness::clamp_set_brightness(brightness);
+                        matrix.lock().await.set_brightness(brightness);
+                        clock_state.update(|state| state.brightness = brightness);
+                    },
 
-                        // Turn off the clock display task
-                        clock_displaying_is_running.store(false, std::sync::atomic::Ordering::Relaxed);
+                    event::EventInner::ShowProgress { id, duration_secs, percent, label, r, g, b } => {
+                        tracing::info!(?id, ?percent, "Showing progress");
+                        let widget = widget::Widget::Progress {
+                            percent,
+                            label,
+                            color: embedded_graphics::pixelcolor::Rgb888::new(r, g, b),
+                        };
+                        widgets.lock().await.show(id, widget, std::time::Duration::from_secs(duration_secs.into()));
+                    },
 
-                        let mut matrix = matrix.lock().await;
-                        matrix
-                            .clear(embedded_graphics::pixelcolor::Rgb888::default())
-                            .unwrap();
+                    event::EventInner::ShowGauge { id, duration_secs, style, min, max, value, label, r, g, b, thresholds } => {
+                        tracing::info!(?id, ?value, "Showing gauge");
+                        let widget = widget::Widget::Gauge {
+                            style,
+                            min,
+                            max,
+                            value,
+                            label,
+                            color: embedded_graphics::pixelcolor::Rgb888::new(r, g, b),
+                            thresholds,
+                        };
+                        widgets.lock().await.show(id, widget, std::time::Duration::from_secs(duration_secs.into()));
+                    },
 
-                        let font = config.display.time_font.into();
-                        let text_style = embedded_graphics::mono_font::MonoTextStyle::new(&font,
-                            embedded_graphics::pixelcolor::Rgb888::new(r,g,b)
-                        );
+                    event::EventInner::ShowChart { id, duration_secs, style, values, min, max, r, g, b, gradient_to } => {
+                        tracing::info!(?id, values = values.len(), "Showing chart");
+                        let widget = widget::Widget::Chart {
+                            style,
+                            values,
+                            min,
+                            max,
+                            color: embedded_graphics::pixelcolor::Rgb888::new(r, g, b),
+                            gradient_to: gradient_to.map(Into::into),
+                        };
+                        widgets.lock().await.show(id, widget, std::time::Duration::from_secs(duration_secs.into()));
+                    },
 
-                        while start_time.elapsed() < duration_secs {
-                            Text::new(&text, offset, text_style).draw(&mut *matrix).unwrap();
-                            matrix.flush()?;
+                    event::EventInner::ShowQrCode { id, duration_secs, text, micro, r, g, b } => {
+                        tracing::info!(?id, ?text, "Showing QR code");
+                        let color = embedded_graphics::pixelcolor::Rgb888::new(r, g, b);
+                        let widget = match widget::Widget::qr_code(&text, color, MATRIX_HEIGHT, micro) {
+                            Ok(widget) => widget,
+                            Err(error) => {
+                                tracing::error!(?error, "Refusing to show QR code: {error}");
+                                let refusal = mqtt::Refusal { id, error: error.to_string() };
+                                if let Err(error) = refusal_sender.send(refusal).await {
+                                    tracing::error!(?error, "Failed to send refusal to channel");
+                                }
+                                continue
+                            }
+                        };
+                        widgets.lock().await.show(id, widget, std::time::Duration::from_secs(duration_secs.into()));
+                    },
 
-                            let _ = render_interval.tick().await;
+                    event::EventInner::Sequence { id, steps, loops } => {
+                        tracing::info!(?id, steps = steps.len(), ?loops, "Playing sequence");
+                        if let Err(error) = sequencer.play(id, steps, loops, event.priority, &templates) {
+                            tracing::error!(?error, "Refusing to play sequence: {error}");
                         }
-
-                        // Turn on the clock display task
-                        clock_displaying_is_running.store(true, std::sync::atomic::Ordering::Relaxed);
-                        drop(matrix)
                     },
 
-                    event::EventInner::ShowPreset { name, duration_s, c1, c2, c3, sx, ix } => {
-                        let effects = wled_client.get(effects_url.clone())
-                            .send()
-                            .await
-                            .map_err(crate::error::Error::Reqwest)?
-                            .json::<Vec<String>>()
-                            .await
-                            .inspect(|response| tracing::debug!(?response, "Successfully asked for effects"))
-                            .inspect_err(|error| tracing::error!(?error, "WLED Client errored"))
-                            .map_err(crate::error::Error::Reqwest)?;
-
-                        let Some(effect_idx) = effects.iter().enumerate().find_map(|(idx, n)| (*n == name).then_some(idx)) else {
-                            tracing::error!("{name} not found in {}", effects.join(", "));
+                    event::EventInner::PlaySequence { name } => {
+                        let Some(sequence) = config.sequences.iter().find(|sequence| sequence.name == name) else {
+                            let error = crate::error::Error::UnknownSequence(name);
+                            tracing::error!(?error, "Refusing to play sequence: {error}");
                             continue
                         };
 
-                        // Turn off the clock display task
-                        clock_displaying_is_running.store(false, std::sync::atomic::Ordering::Relaxed);
+                        tracing::info!(?name, "Playing sequence");
+                        if let Err(error) = sequencer.play(Some(name), sequence.steps.clone(), sequence.loops, event.priority, &templates) {
+                            tracing::error!(?error, "Refusing to play sequence: {error}");
+                        }
+                    },
 
-                        let response = wled_client
-                            .post(state_url.clone())
-                            .json(&wled_api_types::types::state::State {
-                            seg: Some(vec![
-                                wled_api_types::types::state::Seg {
-                                    fx: Some(effect_idx as u16),
-                                    c1,
-                                    c2,
-                                    c3: c3.map(|v| v.clamp(0, 31)),
-                                    sx,
-                                    ix,
-                                    ..Default::default()
-                                }]),
-                                ..Default::default()
-                            })
-                            .send()
-                            .await
-                            .inspect(|response| tracing::debug!(?response, "Successfully flushed state to WLED"))
-                            .inspect_err(|error| tracing::error!(?error, "WLED Client errored"))
-                            .map_err(crate::error::Error::Reqwest)?
-                            .json::<serde_json::Value>()
-                            .await
-                            .map_err(crate::error::Error::Reqwest)?;
+                    event::EventInner::Template { .. } => {
+                        // Templates are expanded above, and cannot expand to templates
+                    },
 
-                        tracing::debug!(?response, "Received JSON response");
-                        tracing::info!("Posted effect {effect_idx} successfully");
+                    event::EventInner::Dismiss { id } => {
+                        if let Some(id) = id.as_deref() {
+                            if sequencer.stop(id) {
+                                tracing::info!(?id, "Stopped sequence");
+                            }
+                        }
 
-                        tokio::time::sleep(std::time::Duration::from_secs(duration_s)).await;
-                        // Turn on the clock display task
-                        clock_displaying_is_running.store(true, std::sync::atomic::Ordering::Relaxed);
+                        if let Err(error) = compositor_sender.send(compositor::Command::Dismiss { id }).await {
+                            tracing::error!(?error, "Failed to send command to compositor");
+                        }
                     },
 
-                    event::EventInner::Json { value, sleep_s } => {
-                        tracing::info!(json = ?value, ?sleep_s, "Sending plain JSON to WLED API");
-
-                        // Turn off the clock display task
-                        clock_displaying_is_running.store(false , std::sync::atomic::Ordering::Relaxed);
-                        let response = wled_client
-                            .post(state_url.clone())
-                            .json(&value)
-                            .send()
-                            .await
-                            .inspect(|response| tracing::debug!(?response, "Successfully flushed state to WLED"))
-                            .inspect_err(|error| tracing::error!(?error, "WLED Client errored"))
-                            .map_err(crate::error::Error::Reqwest)?
-                            .json::<serde_json::Value>()
-                            .await
-                            .map_err(crate::error::Error::Reqwest)?;
+                    event::EventInner::ClearQueue => {
+                        if let Err(error) = compositor_sender.send(compositor::Command::ClearQueue).await {
+                            tracing::error!(?error, "Failed to send command to compositor");
+                        }
+                    },
 
-                        tracing::info!(?response, "Received response from WLED API");
-                        tokio::time::sleep(std::time::Duration::from_secs(sleep_s)).await;
-                        // Turn on the clock display task
-                        clock_displaying_is_running.store(true, std::sync::atomic::Ordering::Relaxed);
+                    event::EventInner::ShowText { .. } | event::EventInner::ShowPreset { .. } | event::EventInner::Json { .. } | event::EventInner::ShowEffect { .. } => {
+                        // Overlays are handled above
                     },
                 }
             }
         }
     }
 
+    // Let the MQTT task publish that we are going offline
+    cancellation_token.cancel();
+    match mqtt_task.await {
+        Ok(Ok(())) => {}
+        Ok(Err(error)) => tracing::error!(?error, "MQTT processing failed"),
+        Err(error) => tracing::error!(?error, "MQTT task failed"),
+    }
+
     Ok(())
 }
@@ -1,17 +1,63 @@
+use std::sync::Arc;
+
+use rumqttc::v5::mqttbytes::v5::LastWill;
+use rumqttc::v5::mqttbytes::v5::PublishProperties;
+use rumqttc::v5::mqttbytes::QoS;
+use rumqttc::v5::AsyncClient;
+use rumqttc::v5::EventLoop;
 use rumqttc::v5::MqttOptions;
+use tokio::sync::Mutex;
 use tokio_util::sync::CancellationToken;
 
-use crate::error::MqttError;
+const ONLINE: &str = "online";
+const OFFLINE: &str = "offline";
+
+/// How long to wait before reconnecting to the broker
+const RECONNECT_DELAY: std::time::Duration = std::time::Duration::from_secs(5);
 
+/// How long publishing that we are offline may take on shutdown
+const SHUTDOWN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// An event that was received but refused when processing it
+#[derive(Debug)]
+pub struct Refusal {
+    /// `id` of the refused event
+    pub id: String,
+    pub error: String,
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn run(
     config: crate::config::MqttConfig,
     cancellation_token: CancellationToken,
     event_sender: tokio::sync::mpsc::Sender<crate::event::Event>,
+    sensor_values: Arc<Mutex<crate::sensor::SensorValues>>,
+    ambient_light: Option<Arc<Mutex<crate::ambient::AmbientLight>>>,
+    mut clock_state: tokio::sync::watch::Receiver<crate::state::ClockState>,
+    discovery_configs: Vec<(String, serde_json::Value)>,
+    mut refusals: tokio::sync::mpsc::Receiver<Refusal>,
 ) -> Result<(), crate::error::MqttError> {
-    let mut mqttoptions =
-        MqttOptions::new(&config.client_name, config.host.to_string(), config.port);
+    // rumqttc resolves hostnames whenever it connects
+    let host = broker_host(&config);
+    let mut mqttoptions = MqttOptions::new(&config.client_name, host.clone(), config.port);
     mqttoptions.set_keep_alive(config.keep_alive);
 
+    // Published by the broker if we disappear without disconnecting
+    let availability_topic = format!("{prefix}/availability", prefix = config.topic_prefix);
+    let qos = QoS::from(config.qos);
+    mqttoptions.set_last_will(LastWill::new(&availability_topic, OFFLINE, qos, true, None));
+
+    if let Some(tls_config) = config.tls.as_ref() {
+        let client_config = crate::tls::client_config(tls_config, &host)
+            .inspect_err(|error| tracing::error!(?error, "Failed to set up TLS"))
+            .map_err(crate::error::MqttError::Tls)?;
+
+        tracing::debug!("Using TLS for MQTT connection");
+        mqttoptions.set_transport(rumqttc::Transport::Tls(rumqttc::TlsConfiguration::Rustls(
+            Arc::new(client_config),
+        )));
+    }
+
     if let (Some(username), Some(password)) = (config.username.as_ref(), config.password.as_ref()) {
         tracing::debug!(
             ?username,
@@ -22,65 +68,160 @@ pub async fn run(
         tracing::debug!("Using no username/password for MQTT connection");
     }
 
-    let (client, mut eventloop) = rumqttc::v5::AsyncClient::new(mqttoptions, 100);
+    let (client, mut eventloop) = AsyncClient::new(mqttoptions, 100);
 
     let topic = format!("{prefix}/events", prefix = config.topic_prefix);
-    let qos = rumqttc::v5::mqttbytes::QoS::from(config.qos);
+    let errors_topic = format!("{prefix}/errors", prefix = config.topic_prefix);
+    let state_topic = format!("{prefix}/state", prefix = config.topic_prefix);
 
-    let Some(sub_result) = cancellation_token
-        .run_until_cancelled(client.subscribe(&topic, qos))
-        .await
-    else {
-        tracing::info!("Cancelled, shutting down MQTT processing");
-        return Ok(());
-    };
+    let mut subscriptions = vec![topic.clone()];
+    subscriptions.extend(config.sensors.iter().map(|sensor| sensor.topic.clone()));
+    if let Some(ambient_light) = ambient_light.as_ref() {
+        subscriptions.push(ambient_light.lock().await.topic().to_string());
+    }
 
-    sub_result
-        .inspect_err(|error| tracing::error!(?error, "Failed to subscribe to topic"))
-        .map_err(crate::error::MqttError::Subscribing)?;
+    loop {
+        let event = tokio::select! {
+            _cancelled = cancellation_token.cancelled() => {
+                tracing::info!("cancelled, shutting down MQTT processing");
+                break;
+            }
 
-    tracing::info!("Successfully subscribed to {topic}");
+            changed = clock_state.changed() => {
+                if changed.is_err() {
+                    tracing::info!("State closed, shutting down MQTT processing");
+                    break;
+                }
 
-    loop {
-        let event = cancellation_token
-            .run_until_cancelled(eventloop.poll())
-            .await;
+                publish_state(&client, qos, &state_topic, &clock_state.borrow_and_update());
+                continue;
+            }
+
+            Some(refusal) = refusals.recv() => {
+                let response = Response::Refused {
+                    id: refusal.id,
+                    error: refusal.error,
+                };
+                respond(&client, qos, &errors_topic, None, &response);
+                continue;
+            }
 
-        let Some(event) = event else {
-            tracing::info!("cancelled, shutting down MQTT processing");
-            break;
+            event = eventloop.poll() => event,
         };
 
-        let event = event.map_err(MqttError::Connection)?;
+        let event = match event {
+            Ok(event) => event,
+            Err(error) => {
+                // The host is resolved again when reconnecting on the next poll
+                tracing::error!(?error, "MQTT connection failed, reconnecting");
+                let wait = tokio::time::sleep(RECONNECT_DELAY);
+                if cancellation_token.run_until_cancelled(wait).await.is_none() {
+                    tracing::info!("cancelled, shutting down MQTT processing");
+                    break;
+                }
+                continue;
+            }
+        };
 
         match event {
+            rumqttc::v5::Event::Incoming(rumqttc::v5::Incoming::ConnAck(_)) => {
+                tracing::info!("Connected to MQTT broker");
+
+                // Subscriptions do not outlive the session, which ends with the connection
+                for subscription in subscriptions.iter() {
+                    client
+                        .try_subscribe(subscription, qos)
+                        .inspect_err(|error| {
+                            tracing::error!(?error, "Failed to subscribe to topic")
+                        })
+                        .map_err(crate::error::MqttError::Subscribing)?;
+                    tracing::info!("Subscribing to {subscription}");
+                }
+
+                for (discovery_topic, discovery_config) in discovery_configs.iter() {
+                    tracing::debug!(
+                        ?discovery_topic,
+                        "Publishing Home Assistant discovery config"
+                    );
+                    if let Err(error) =
+                        client.try_publish(discovery_topic, qos, true, discovery_config.to_string())
+                    {
+                        tracing::error!(
+                            ?error,
+                            "Failed to publish Home Assistant discovery config"
+                        );
+                    }
+                }
+                publish_state(&client, qos, &state_topic, &clock_state.borrow_and_update());
+
+                if let Err(error) = client.try_publish(&availability_topic, qos, true, ONLINE) {
+                    tracing::error!(?error, "Failed to publish availability");
+                }
+            }
+
             rumqttc::v5::Event::Incoming(rumqttc::v5::Incoming::Publish(
                 rumqttc::v5::mqttbytes::v5::Publish {
                     dup: _,
                     qos: _,
                     retain: _,
-                    topic,
+                    topic: publish_topic,
                     pkid: _,
                     payload,
-                    properties: _,
+                    properties,
                 },
             )) => {
-                tracing::debug!(?topic, ?payload, "Received payload");
+                tracing::debug!(topic = ?publish_topic, ?payload, "Received payload");
+
+                if publish_topic != topic.as_bytes() {
+                    let Ok(publish_topic) = std::str::from_utf8(&publish_topic) else {
+                        tracing::debug!(topic = ?publish_topic, "Topic is not valid UTF-8, ignoring");
+                        continue;
+                    };
+
+                    let is_ambient_light = match ambient_light.as_ref() {
+                        Some(ambient_light) => {
+                            ambient_light.lock().await.update(publish_topic, &payload)
+                        }
+                        None => false,
+                    };
+                    let is_sensor = sensor_values.lock().await.update(publish_topic, &payload);
+
+                    if !is_ambient_light && !is_sensor {
+                        tracing::debug!(?publish_topic, "No sensor for topic, ignoring");
+                    }
+                    continue;
+                }
 
-                let event: crate::event::Event = match serde_json::from_slice(&payload) {
+                let payload_hash = payload_hash(&payload);
+                let response = match serde_json::from_slice::<crate::event::Event>(&payload) {
                     Ok(event) => {
                         tracing::debug!(?event, "Deserialized event successfully");
-                        event
+                        match event_sender.send(event).await {
+                            Ok(()) => Response::Ok { payload_hash },
+                            Err(error) => {
+                                tracing::error!(
+                                    event = ?error.0,
+                                    "Failed to send event to internal channel"
+                                );
+                                Response::Error {
+                                    payload_hash,
+                                    error: error.to_string(),
+                                    path: None,
+                                }
+                            }
+                        }
                     }
                     Err(error) => {
-                        tracing::debug!(?error, "Failed to deserialize event, ignoring");
-                        continue;
+                        tracing::warn!(?error, "Failed to deserialize event, ignoring");
+                        Response::Error {
+                            payload_hash,
+                            error: error.to_string(),
+                            path: crate::schema::error_path(&payload),
+                        }
                     }
                 };
 
-                if let Err(event) = event_sender.send(event).await {
-                    tracing::error!(?event, "Failed to send event to internal channel");
-                }
+                respond(&client, qos, &errors_topic, properties.as_ref(), &response);
             }
 
             rumqttc::v5::Event::Incoming(_) => {
@@ -93,5 +234,226 @@ pub async fn run(
         }
     }
 
+    shut_down(&client, &mut eventloop, qos, &availability_topic).await;
     Ok(())
 }
+
+/// The host rumqttc connects to
+///
+/// With TLS, rumqttc sends the host as server name and verifies the broker certificate against
+/// it, so the configured server name is connected to instead. IPv6 addresses are passed without
+/// brackets, which are not valid in a server name and not needed to connect.
+fn broker_host(config: &crate::config::MqttConfig) -> String {
+    config
+        .tls
+        .as_ref()
+        .and_then(|tls| tls.server_name.as_ref())
+        .unwrap_or(&config.host)
+        .unbracketed()
+}
+
+/// Publish that we are offline and disconnect, the broker does not publish the last will then
+async fn shut_down(
+    client: &AsyncClient,
+    eventloop: &mut EventLoop,
+    qos: QoS,
+    availability_topic: &str,
+) {
+    if let Err(error) = client.try_publish(availability_topic, qos, true, OFFLINE) {
+        tracing::error!(?error, "Failed to publish availability");
+    }
+    if let Err(error) = client.try_disconnect() {
+        tracing::error!(?error, "Failed to disconnect");
+        return;
+    }
+
+    let disconnected = async {
+        loop {
+            match eventloop.poll().await {
+                Ok(rumqttc::v5::Event::Outgoing(rumqttc::Outgoing::Disconnect)) => break,
+                Ok(_) => {}
+                Err(error) => {
+                    tracing::error!(?error, "Connection failed while disconnecting");
+                    break;
+                }
+            }
+        }
+    };
+
+    if tokio::time::timeout(SHUTDOWN_TIMEOUT, disconnected)
+        .await
+        .is_err()
+    {
+        tracing::warn!("Timed out disconnecting");
+    }
+}
+
+/// Publish `state` retained, so dashboards get it as soon as they subscribe
+fn publish_state(
+    client: &AsyncClient,
+    qos: QoS,
+    state_topic: &str,
+    state: &crate::state::ClockState,
+) {
+    tracing::debug!(?state, "Publishing state");
+    let payload = match serde_json::to_vec(state) {
+        Ok(payload) => payload,
+        Err(error) => {
+            tracing::error!(?error, "Failed to serialize state");
+            return;
+        }
+    };
+
+    if let Err(error) = client.try_publish(state_topic, qos, true, payload) {
+        tracing::error!(?error, "Failed to publish state");
+    }
+}
+
+/// Answer to an event, published to `{prefix}/errors` if it failed and to the response topic of
+/// the event if it has one
+///
+/// Events are refused after they were answered already, so refusals only go to `{prefix}/errors`.
+#[derive(Debug, serde::Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+enum Response {
+    Ok {
+        payload_hash: String,
+    },
+    Error {
+        payload_hash: String,
+        error: String,
+
+        /// JSON pointer to the value that could not be deserialized
+        path: Option<String>,
+    },
+
+    /// The event was received, but could not be shown
+    Refused {
+        id: String,
+        error: String,
+    },
+}
+
+/// FNV-1a hash of `payload`, so errors can be matched to events without repeating them
+fn payload_hash(payload: &[u8]) -> String {
+    let hash = payload.iter().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    });
+    format!("{hash:016x}")
+}
+
+fn respond(
+    client: &AsyncClient,
+    qos: QoS,
+    errors_topic: &str,
+    properties: Option<&PublishProperties>,
+    response: &Response,
+) {
+    let payload = match serde_json::to_vec(response) {
+        Ok(payload) => payload,
+        Err(error) => {
+            tracing::error!(?error, "Failed to serialize response");
+            return;
+        }
+    };
+
+    // Publishing is not awaited, the client waits for the event loop which is only polled by us
+    if !std::matches!(response, Response::Ok { .. }) {
+        if let Err(error) = client.try_publish(errors_topic, qos, false, payload.clone()) {
+            tracing::error!(?error, "Failed to publish error");
+        }
+    }
+
+    let Some(properties) = properties else {
+        return;
+    };
+    let Some(response_topic) = properties.response_topic.as_ref() else {
+        return;
+    };
+
+    let response_properties = PublishProperties {
+        correlation_data: properties.correlation_data.clone(),
+        ..Default::default()
+    };
+    if let Err(error) =
+        client.try_publish_with_properties(response_topic, qos, false, payload, response_properties)
+    {
+        tracing::error!(?error, ?response_topic, "Failed to publish response");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_payload_hash() {
+        assert_eq!(super::payload_hash(b""), "cbf29ce484222325");
+        assert_eq!(super::payload_hash(b"a"), "af63dc4c8601ec8c");
+    }
+
+    #[test]
+    fn test_error_response() {
+        let response = super::Response::Error {
+            payload_hash: super::payload_hash(b"{}"),
+            error: String::from("missing field `event` at line 1 column 2"),
+            path: Some(String::new()),
+        };
+
+        insta::assert_json_snapshot!(response, @r#"
+        {
+          "status": "error",
+          "payload_hash": "08f44b07b5901a25",
+          "error": "missing field `event` at line 1 column 2",
+          "path": ""
+        }
+        "#);
+    }
+
+    #[test]
+    fn test_refused_response() {
+        let response = super::Response::Refused {
+            id: String::from("guest-wifi"),
+            error: String::from("No QR code fits into 16x16 modules"),
+        };
+
+        insta::assert_json_snapshot!(response, @r#"
+        {
+          "status": "refused",
+          "id": "guest-wifi",
+          "error": "No QR code fits into 16x16 modules"
+        }
+        "#);
+    }
+
+    #[test]
+    fn test_tls_with_ipv6_host() {
+        let config = |tls: &str| -> crate::config::MqttConfig {
+            toml::from_str(&format!(
+                r#"
+                host = "[::1]"
+                port = 8883
+                qos = "AtMostOnce"
+                client_name = "clock"
+                topic_prefix = "clock"
+                keep_alive = "10s"
+
+                [tls]
+                ca_file = "{dir}/test/tls/ca.pem"
+                {tls}
+                "#,
+                dir = env!("CARGO_MANIFEST_DIR"),
+            ))
+            .unwrap()
+        };
+
+        let ipv6 = config("");
+        let host = super::broker_host(&ipv6);
+        assert_eq!(host, "::1");
+        assert!(crate::tls::client_config(ipv6.tls.as_ref().unwrap(), &host).is_ok());
+
+        let server_name = config(r#"server_name = "mqtt.test""#);
+        assert_eq!(super::broker_host(&server_name), "mqtt.test");
+
+        let ipv6_server_name = config(r#"server_name = "[::2]""#);
+        assert_eq!(super::broker_host(&ipv6_server_name), "::2");
+    }
+}
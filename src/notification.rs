@@ -0,0 +1,144 @@
+use std::collections::VecDeque;
+
+use crate::event::Event;
+
+/// Overlay events waiting to be shown, ordered by priority
+///
+/// Events with the same priority are shown in the order they arrived.
+#[derive(Debug, Default)]
+pub struct NotificationQueue {
+    queued: VecDeque<Event>,
+}
+
+impl NotificationQueue {
+    /// Queue `event`, replacing a queued event with the same `id`
+    pub fn push(&mut self, event: Event) {
+        if let Some(id) = event.event.overlay_id() {
+            if self.remove(id) {
+                tracing::debug!(?id, "Replacing queued overlay");
+            }
+        }
+
+        let idx = self
+            .queued
+            .iter()
+            .position(|queued| queued.priority < event.priority)
+            .unwrap_or(self.queued.len());
+        self.queued.insert(idx, event);
+    }
+
+    /// Queue `event` before the queued events with the same priority
+    ///
+    /// Used for overlays that were preempted, so that they continue as soon as possible.
+    pub fn push_front(&mut self, event: Event) {
+        let idx = self
+            .queued
+            .iter()
+            .position(|queued| queued.priority <= event.priority)
+            .unwrap_or(self.queued.len());
+        self.queued.insert(idx, event);
+    }
+
+    pub fn pop(&mut self) -> Option<Event> {
+        self.queued.pop_front()
+    }
+
+    /// Remove the queued event with `id`, returns whether there was one
+    pub fn remove(&mut self, id: &str) -> bool {
+        let len = self.queued.len();
+        self.queued
+            .retain(|queued| queued.event.overlay_id() != Some(id));
+        self.queued.len() != len
+    }
+
+    /// Remove all queued events, returns how many there were
+    pub fn clear(&mut self) -> usize {
+        let len = self.queued.len();
+        self.queued.clear();
+        len
+    }
+
+    pub fn len(&self) -> usize {
+        self.queued.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NotificationQueue;
+    use crate::event::Event;
+    use crate::event::EventInner;
+    use crate::event::Priority;
+
+    fn event(sleep_s: u64, priority: Priority) -> Event {
+        with_id(None, sleep_s, priority)
+    }
+
+    fn with_id(id: Option<&str>, sleep_s: u64, priority: Priority) -> Event {
+        Event {
+            event: EventInner::Json {
+                id: id.map(ToString::to_string),
+                value: serde_json::Value::Null,
+                sleep_s,
+            },
+            priority,
+        }
+    }
+
+    fn sleep_s(event: Option<Event>) -> Option<u64> {
+        match event?.event {
+            EventInner::Json { sleep_s, .. } => Some(sleep_s),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_higher_priority_first_then_arrival_order() {
+        let mut queue = NotificationQueue::default();
+        queue.push(event(1, Priority::Normal));
+        queue.push(event(2, Priority::Low));
+        queue.push(event(3, Priority::High));
+        queue.push(event(4, Priority::Normal));
+        queue.push(event(5, Priority::High));
+        assert_eq!(queue.len(), 5);
+
+        let order = std::iter::from_fn(|| sleep_s(queue.pop())).collect::<Vec<_>>();
+        assert_eq!(order, [3, 5, 1, 4, 2]);
+    }
+
+    #[test]
+    fn test_push_front_before_same_priority() {
+        let mut queue = NotificationQueue::default();
+        queue.push(event(1, Priority::High));
+        queue.push(event(2, Priority::Normal));
+        queue.push(event(3, Priority::Low));
+        queue.push_front(event(4, Priority::Normal));
+
+        let order = std::iter::from_fn(|| sleep_s(queue.pop())).collect::<Vec<_>>();
+        assert_eq!(order, [1, 4, 2, 3]);
+    }
+
+    #[test]
+    fn test_same_id_replaces() {
+        let mut queue = NotificationQueue::default();
+        queue.push(with_id(Some("a"), 1, Priority::Normal));
+        queue.push(event(2, Priority::Normal));
+        queue.push(with_id(Some("a"), 3, Priority::High));
+
+        let order = std::iter::from_fn(|| sleep_s(queue.pop())).collect::<Vec<_>>();
+        assert_eq!(order, [3, 2]);
+    }
+
+    #[test]
+    fn test_remove_and_clear() {
+        let mut queue = NotificationQueue::default();
+        queue.push(with_id(Some("a"), 1, Priority::Normal));
+        queue.push(event(2, Priority::Normal));
+
+        assert!(!queue.remove("b"));
+        assert!(queue.remove("a"));
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.clear(), 1);
+        assert!(queue.pop().is_none());
+    }
+}
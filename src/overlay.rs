@@ -0,0 +1,295 @@
+use std::sync::Arc;
+
+use embedded_graphics::geometry::Point;
+use embedded_graphics::prelude::DrawTarget;
+use embedded_graphics::text::Text;
+use embedded_graphics::Drawable;
+use tokio::sync::Mutex;
+use url::Url;
+
+use crate::compositor::OverlayFuture;
+use crate::compositor::Player;
+use crate::config::Font;
+use crate::effect::EffectRenderer;
+use crate::event::EventInner;
+use crate::layer::Layer;
+use crate::layer::Layers;
+use crate::state::ClockRunning;
+
+/// Shows overlay events (`ShowText`, `ShowPreset`, `Json`) for their duration in the
+/// notifications layer or via the WLED API
+///
+/// The clock is paused while WLED shows an overlay itself.
+#[derive(Clone)]
+pub struct OverlayPlayer {
+    wled_client: reqwest::Client,
+    state_url: Url,
+    effects_url: Url,
+    layers: Arc<Mutex<Layers>>,
+    clock_running: Arc<ClockRunning>,
+    font: Font,
+    frame_interval: std::time::Duration,
+}
+
+impl Player for OverlayPlayer {
+    fn play(&self, event: EventInner) -> OverlayFuture {
+        let player = self.clone();
+        Box::pin(async move { player.show(event).await })
+    }
+
+    async fn finish(&self) {
+        self.layers
+            .lock()
+            .await
+            .get_mut(Layer::Notifications)
+            .clear_transparent();
+        self.resume_clock();
+    }
+}
+
+impl OverlayPlayer {
+    pub fn new(
+        wled_client: reqwest::Client,
+        state_url: Url,
+        effects_url: Url,
+        layers: Arc<Mutex<Layers>>,
+        clock_running: Arc<ClockRunning>,
+        display_config: &crate::config::DisplayConfig,
+    ) -> Self {
+        Self {
+            wled_client,
+            state_url,
+            effects_url,
+            layers,
+            clock_running,
+            font: display_config.time_font,
+            frame_interval: display_config.frame_interval(),
+        }
+    }
+
+    /// The clock only runs again if the display is on
+    fn resume_clock(&self) {
+        self.clock_running.set_paused(false);
+    }
+
+    fn pause_clock(&self) {
+        self.clock_running.set_paused(true);
+    }
+
+    async fn show(&self, event: EventInner) -> Result<(), crate::error::Error> {
+        match event {
+            EventInner::ShowText {
+                id: _,
+                duration_secs,
+                text,
+                r,
+                g,
+                b,
+                x,
+                y,
+            } => {
+                tracing::info!(?duration_secs, ?text, "Showing text");
+
+                let font = self.font.into();
+                let text_style = embedded_graphics::mono_font::MonoTextStyle::new(
+                    &font,
+                    embedded_graphics::pixelcolor::Rgb888::new(r, g, b),
+                );
+
+                {
+                    let mut layers = self.layers.lock().await;
+                    let notifications = layers.get_mut(Layer::Notifications);
+                    notifications
+                        .clear(embedded_graphics::pixelcolor::Rgb888::default())
+                        .unwrap();
+                    Text::new(&text, Point::new(x.into(), y.into()), text_style)
+                        .draw(&mut *notifications)
+                        .unwrap();
+                }
+
+                tokio::time::sleep(std::time::Duration::from_secs(duration_secs.into())).await;
+            }
+
+            EventInner::ShowEffect {
+                id: _,
+                effect,
+                duration_secs,
+                seed,
+            } => {
+                tracing::info!(?effect, ?duration_secs, ?seed, "Showing effect");
+
+                let mut renderer =
+                    EffectRenderer::new(effect, seed, self.layers.lock().await.size());
+                let mut frame_interval = tokio::time::interval(self.frame_interval);
+                let start_time = std::time::Instant::now();
+                let duration_secs = std::time::Duration::from_secs(duration_secs.into());
+
+                while start_time.elapsed() < duration_secs {
+                    // Not locked across the wait, so that the frame can be sent to the display
+                    renderer.render(self.layers.lock().await.get_mut(Layer::Notifications));
+                    let _ = frame_interval.tick().await;
+                }
+            }
+
+            EventInner::ShowPreset {
+                id: _,
+                name,
+                duration_s,
+                c1,
+                c2,
+                c3,
+                sx,
+                ix,
+            } => {
+                let effects = self
+                    .wled_client
+                    .get(self.effects_url.clone())
+                    .send()
+                    .await
+                    .map_err(crate::error::Error::Reqwest)?
+                    .json::<Vec<String>>()
+                    .await
+                    .inspect(|response| {
+                        tracing::debug!(?response, "Successfully asked for effects")
+                    })
+                    .inspect_err(|error| tracing::error!(?error, "WLED Client errored"))
+                    .map_err(crate::error::Error::Reqwest)?;
+
+                let Some(effect_idx) = effects
+                    .iter()
+                    .enumerate()
+                    .find_map(|(idx, n)| (*n == name).then_some(idx))
+                else {
+                    tracing::error!("{name} not found in {}", effects.join(", "));
+                    return Ok(());
+                };
+
+                self.pause_clock();
+
+                let response = self
+                    .wled_client
+                    .post(self.state_url.clone())
+                    .json(&wled_api_types::types::state::State {
+                        seg: Some(vec![wled_api_types::types::state::Seg {
+                            fx: Some(effect_idx as u16),
+                            c1,
+                            c2,
+                            c3: c3.map(|v| v.clamp(0, 31)),
+                            sx,
+                            ix,
+                            ..Default::default()
+                        }]),
+                        ..Default::default()
+                    })
+                    .send()
+                    .await
+                    .inspect(|response| {
+                        tracing::debug!(?response, "Successfully flushed state to WLED")
+                    })
+                    .inspect_err(|error| tracing::error!(?error, "WLED Client errored"))
+                    .map_err(crate::error::Error::Reqwest)?
+                    .json::<serde_json::Value>()
+                    .await
+                    .map_err(crate::error::Error::Reqwest)?;
+
+                tracing::debug!(?response, "Received JSON response");
+                tracing::info!("Posted effect {effect_idx} successfully");
+
+                tokio::time::sleep(std::time::Duration::from_secs(duration_s)).await;
+            }
+
+            EventInner::Json {
+                id: _,
+                value,
+                sleep_s,
+            } => {
+                tracing::info!(json = ?value, ?sleep_s, "Sending plain JSON to WLED API");
+
+                self.pause_clock();
+                let response = self
+                    .wled_client
+                    .post(self.state_url.clone())
+                    .json(&value)
+                    .send()
+                    .await
+                    .inspect(|response| {
+                        tracing::debug!(?response, "Successfully flushed state to WLED")
+                    })
+                    .inspect_err(|error| tracing::error!(?error, "WLED Client errored"))
+                    .map_err(crate::error::Error::Reqwest)?
+                    .json::<serde_json::Value>()
+                    .await
+                    .map_err(crate::error::Error::Reqwest)?;
+
+                tracing::info!(?response, "Received response from WLED API");
+                tokio::time::sleep(std::time::Duration::from_secs(sleep_s)).await;
+            }
+
+            other => {
+                tracing::warn!(event = ?other, "Not an overlay event, ignoring");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use embedded_graphics::geometry::Size;
+    use tokio::sync::Mutex;
+
+    use super::OverlayPlayer;
+    use crate::compositor::Player;
+    use crate::config::Font;
+    use crate::config::LayerOpacityConfig;
+    use crate::event::EventInner;
+    use crate::layer::Layers;
+    use crate::state::ClockRunning;
+
+    fn player(clock_running: Arc<ClockRunning>) -> OverlayPlayer {
+        OverlayPlayer {
+            wled_client: reqwest::Client::new(),
+            state_url: url::Url::parse("http://wled.test/json/state").unwrap(),
+            effects_url: url::Url::parse("http://wled.test/json/eff").unwrap(),
+            layers: Arc::new(Mutex::new(Layers::new(
+                Size::new(32, 16),
+                &LayerOpacityConfig::default(),
+            ))),
+            clock_running,
+            font: Font::Font5x8,
+            frame_interval: std::time::Duration::from_millis(100),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_overlay_ending_while_off_keeps_clock_stopped() {
+        let clock_running = Arc::new(ClockRunning::new(true));
+        let player = player(clock_running.clone());
+
+        // A preset paused the clock, then the display was turned off
+        player.pause_clock();
+        clock_running.set_on(false);
+
+        player
+            .play(EventInner::ShowText {
+                id: None,
+                duration_secs: 5,
+                text: String::from("Hi"),
+                r: 255,
+                g: 255,
+                b: 255,
+                x: 0,
+                y: 8,
+            })
+            .await
+            .unwrap();
+        player.finish().await;
+        assert!(!clock_running.is_running());
+
+        clock_running.set_on(true);
+        assert!(clock_running.is_running());
+    }
+}
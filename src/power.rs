@@ -0,0 +1,317 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use time::OffsetDateTime;
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::LocationConfig;
+use crate::config::QuietHoursMode;
+use crate::config::ScheduleTime;
+use crate::config::TimeWindowConfig;
+use crate::event::Event;
+use crate::event::EventInner;
+use crate::event::Priority;
+
+/// How often to check whether a window started or ended
+const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// How many notifications are kept during quiet hours, the oldest ones are dropped first
+const MAX_QUEUED: usize = 32;
+
+#[derive(Debug, Clone)]
+pub struct TimeWindows {
+    windows: Vec<TimeWindowConfig>,
+    location: Option<LocationConfig>,
+}
+
+impl TimeWindows {
+    pub fn new(
+        windows: Vec<TimeWindowConfig>,
+        location: Option<LocationConfig>,
+    ) -> Result<Self, crate::error::Error> {
+        let sun_relative = windows.iter().any(|window| {
+            [window.from, window.until]
+                .iter()
+                .any(|at| std::matches!(at, ScheduleTime::Sun { .. }))
+        });
+
+        if sun_relative && location.is_none() {
+            return Err(crate::error::Error::MissingLocation);
+        }
+
+        Ok(Self { windows, location })
+    }
+
+    /// Whether `now` is within any of the windows
+    pub fn contains(&self, now: OffsetDateTime) -> bool {
+        self.windows
+            .iter()
+            .any(|window| self.window_contains(window, now))
+    }
+
+    fn window_contains(&self, window: &TimeWindowConfig, now: OffsetDateTime) -> bool {
+        // A window that started yesterday may last until today
+        let start_dates = [now.date().previous_day(), Some(now.date())];
+
+        start_dates.into_iter().flatten().any(|date| {
+            if !window.days.is_empty() && !window.days.contains(&date.weekday().into()) {
+                return false;
+            }
+
+            let resolve = |at, date| {
+                crate::astro::resolve_schedule_time(at, date, now.offset(), self.location.as_ref())
+            };
+
+            let Some(start) = resolve(window.from, date) else {
+                return false;
+            };
+            let end = resolve(window.until, date)
+                .filter(|end| *end > start)
+                .or_else(|| resolve(window.until, date.next_day()?));
+
+            end.is_some_and(|end| start <= now && now < end)
+        })
+    }
+}
+
+/// Notifications held back during quiet hours
+#[derive(Debug, Default)]
+pub struct QuietHours {
+    /// `None` outside of quiet hours
+    active: Option<QuietHoursMode>,
+    queued: VecDeque<Event>,
+}
+
+impl QuietHours {
+    /// Returns `event` if it may be handled now, otherwise it is queued or dropped
+    ///
+    /// Control events and `High` priority events are never held back.
+    pub fn admit(&mut self, event: Event) -> Option<Event> {
+        let Some(mode) = self.active else {
+            return Some(event);
+        };
+
+        if !event.event.is_notification() || event.priority == Priority::High {
+            return Some(event);
+        }
+
+        match mode {
+            QuietHoursMode::Queue => {
+                if self.queued.len() >= MAX_QUEUED {
+                    let dropped = self.queued.pop_front();
+                    tracing::warn!(?dropped, "Too many notifications queued, dropping oldest");
+                }
+
+                tracing::info!(?event, "Quiet hours, queueing notification");
+                self.queued.push_back(event);
+            }
+
+            QuietHoursMode::Suppress => {
+                tracing::info!(?event, "Quiet hours, suppressing notification");
+            }
+        }
+
+        None
+    }
+
+    /// Start or end quiet hours
+    ///
+    /// Returns the queued notifications if quiet hours ended.
+    fn set_active(&mut self, mode: Option<QuietHoursMode>) -> Vec<Event> {
+        let ended = self.active.is_some() && mode.is_none();
+        self.active = mode;
+
+        if ended {
+            self.queued.drain(..).collect()
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+/// Turns the display off and on according to `display.power_schedule`, and starts and ends
+/// `display.quiet_hours`
+pub struct PowerTask {
+    power_schedule: TimeWindows,
+    quiet_hours_windows: Option<(QuietHoursMode, TimeWindows)>,
+    cancellation_token: CancellationToken,
+    event_sender: tokio::sync::mpsc::Sender<Event>,
+    quiet_hours: Arc<Mutex<QuietHours>>,
+}
+
+impl PowerTask {
+    pub fn new(
+        config: &crate::config::Config,
+        cancellation_token: CancellationToken,
+        event_sender: tokio::sync::mpsc::Sender<Event>,
+        quiet_hours: Arc<Mutex<QuietHours>>,
+    ) -> Result<Self, crate::error::Error> {
+        let quiet_hours_windows = config
+            .display
+            .quiet_hours
+            .as_ref()
+            .map(|quiet_hours| {
+                TimeWindows::new(quiet_hours.windows.clone(), config.location)
+                    .map(|windows| (quiet_hours.mode, windows))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            power_schedule: TimeWindows::new(
+                config.display.power_schedule.clone(),
+                config.location,
+            )?,
+            quiet_hours_windows,
+            cancellation_token,
+            event_sender,
+            quiet_hours,
+        })
+    }
+
+    pub async fn run(self) -> Result<(), crate::error::Error> {
+        let mut check_interval = tokio::time::interval(CHECK_INTERVAL);
+
+        // Only changes are applied, so that "TurnOn" and "TurnOff" sent in between are kept
+        let mut was_off = None;
+
+        loop {
+            let Some(_tick) = self
+                .cancellation_token
+                .run_until_cancelled(check_interval.tick())
+                .await
+            else {
+                tracing::info!("Ending power schedule");
+                break;
+            };
+
+            let now = OffsetDateTime::now_local().map_err(crate::error::Error::TimeOffset)?;
+
+            let off = self.power_schedule.contains(now);
+            let power_event = match (was_off, off) {
+                (None | Some(false), true) => Some(EventInner::TurnOff),
+                (Some(true), false) => Some(EventInner::TurnOn),
+                _ => None,
+            };
+            was_off = Some(off);
+
+            let quiet_hours_mode = self
+                .quiet_hours_windows
+                .as_ref()
+                .and_then(|(mode, windows)| windows.contains(now).then_some(*mode));
+            let released = self.quiet_hours.lock().await.set_active(quiet_hours_mode);
+
+            let events = power_event
+                .map(Event::new)
+                .into_iter()
+                .chain(released.into_iter());
+
+            for event in events {
+                tracing::info!(?event, "Sending scheduled event");
+                if let Err(error) = self.event_sender.send(event).await {
+                    tracing::error!(?error, "Failed to send event to channel");
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::datetime;
+    use time::macros::time;
+
+    use super::QuietHours;
+    use super::TimeWindows;
+    use crate::config::QuietHoursMode;
+    use crate::config::ScheduleTime;
+    use crate::config::TimeWindowConfig;
+    use crate::config::Weekday;
+    use crate::event::Event;
+    use crate::event::EventInner;
+    use crate::event::Priority;
+
+    fn weekday_nights() -> TimeWindows {
+        TimeWindows::new(
+            vec![TimeWindowConfig {
+                from: ScheduleTime::Clock { at: time!(23:00) },
+                until: ScheduleTime::Clock { at: time!(06:30) },
+                days: vec![
+                    Weekday::Monday,
+                    Weekday::Tuesday,
+                    Weekday::Wednesday,
+                    Weekday::Thursday,
+                    Weekday::Friday,
+                ],
+            }],
+            None,
+        )
+        .unwrap()
+    }
+
+    fn notification() -> Event {
+        Event::new(EventInner::Json {
+            id: None,
+            value: serde_json::Value::Null,
+            sleep_s: 1,
+        })
+    }
+
+    #[test]
+    fn test_window_across_midnight() {
+        let windows = weekday_nights();
+
+        // 2025-10-20 is a Monday
+        assert!(!windows.contains(datetime!(2025-10-20 22:59 UTC)));
+        assert!(windows.contains(datetime!(2025-10-20 23:00 UTC)));
+        assert!(windows.contains(datetime!(2025-10-21 06:29 UTC)));
+        assert!(!windows.contains(datetime!(2025-10-21 06:30 UTC)));
+    }
+
+    #[test]
+    fn test_window_days_are_start_days() {
+        let windows = weekday_nights();
+
+        // Started on Friday
+        assert!(windows.contains(datetime!(2025-10-25 03:00 UTC)));
+
+        // Would have started on Saturday
+        assert!(!windows.contains(datetime!(2025-10-26 03:00 UTC)));
+        assert!(!windows.contains(datetime!(2025-10-25 23:30 UTC)));
+    }
+
+    #[test]
+    fn test_quiet_hours_queue_notifications() {
+        let mut quiet_hours = QuietHours::default();
+        assert!(quiet_hours
+            .set_active(Some(QuietHoursMode::Queue))
+            .is_empty());
+
+        assert!(quiet_hours.admit(notification()).is_none());
+        assert!(quiet_hours
+            .admit(Event::new(EventInner::SetBrightness(10)))
+            .is_some());
+
+        let released = quiet_hours.set_active(None);
+        assert_eq!(released.len(), 1);
+        assert!(quiet_hours.admit(notification()).is_some());
+    }
+
+    #[test]
+    fn test_quiet_hours_suppress_notifications() {
+        let mut quiet_hours = QuietHours::default();
+        quiet_hours.set_active(Some(QuietHoursMode::Suppress));
+
+        assert!(quiet_hours.admit(notification()).is_none());
+
+        let priority = Event {
+            priority: Priority::High,
+            ..notification()
+        };
+        assert!(quiet_hours.admit(priority).is_some());
+
+        assert!(quiet_hours.set_active(None).is_empty());
+    }
+}
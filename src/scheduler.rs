@@ -0,0 +1,300 @@
+use croner::parser::CronParser;
+use croner::parser::Seconds;
+use croner::Cron;
+use time::OffsetDateTime;
+use time::PrimitiveDateTime;
+use time_tz::OffsetDateTimeExt;
+use time_tz::PrimitiveDateTimeExt;
+use time_tz::Tz;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::ScheduleConfig;
+use crate::event::Event;
+
+/// Upper bound for sleeping between checks, so that changes of the system clock are noticed
+const MAX_SLEEP: std::time::Duration = std::time::Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    config: ScheduleConfig,
+    cron: Cron,
+    timezone: &'static Tz,
+}
+
+impl Schedule {
+    /// Schedules without a timezone are evaluated in `local_timezone`
+    pub fn new(
+        config: ScheduleConfig,
+        local_timezone: &'static Tz,
+    ) -> Result<Self, crate::error::Error> {
+        let cron = CronParser::builder()
+            .seconds(Seconds::Disallowed)
+            .build()
+            .parse(&config.cron)
+            .map_err(|source| crate::error::Error::Cron {
+                name: config.name.clone(),
+                source,
+            })?;
+
+        let timezone = match config.timezone.as_deref() {
+            Some(name) => time_tz::timezones::get_by_name(name)
+                .ok_or_else(|| crate::error::Error::UnknownTimezone(name.to_string()))?,
+            None => local_timezone,
+        };
+
+        Ok(Self {
+            config,
+            cron,
+            timezone,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    /// The first time the schedule fires strictly after `after`
+    ///
+    /// Times that do not exist in the timezone because of a daylight saving time change are
+    /// skipped, times that exist twice fire on the first occurrence.
+    pub fn next_after(&self, after: OffsetDateTime) -> Option<OffsetDateTime> {
+        let zoned = after.to_timezone(self.timezone);
+        let mut wall_time = PrimitiveDateTime::new(zoned.date(), zoned.time());
+        loop {
+            wall_time = self.next_wall_time_after(wall_time)?;
+            let next = wall_time
+                .assume_timezone(self.timezone)
+                .take_first()
+                .filter(|next| *next > after);
+            if let Some(next) = next {
+                return Some(next);
+            }
+        }
+    }
+
+    /// The first wall clock time strictly after `after` that matches the cron expression
+    fn next_wall_time_after(&self, after: PrimitiveDateTime) -> Option<PrimitiveDateTime> {
+        // Evaluated in UTC, where every wall clock time exists exactly once
+        let after = chrono::DateTime::from_timestamp(after.assume_utc().unix_timestamp(), 0)?;
+        let next = self.cron.find_next_occurrence(&after, false).ok()?;
+        let next = OffsetDateTime::from_unix_timestamp(next.timestamp()).ok()?;
+        Some(PrimitiveDateTime::new(next.date(), next.time()))
+    }
+
+    fn event(&self) -> Event {
+        Event {
+            event: self.config.event.clone(),
+            priority: self.config.priority,
+        }
+    }
+}
+
+pub fn load(configs: &[ScheduleConfig]) -> Result<Vec<Schedule>, crate::error::Error> {
+    if configs.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let local_timezone =
+        time_tz::system::get_timezone().map_err(crate::error::Error::SystemTimezone)?;
+    configs
+        .iter()
+        .cloned()
+        .map(|config| Schedule::new(config, local_timezone))
+        .collect()
+}
+
+/// Print the next `count` fire times of every schedule
+pub fn list(configs: &[ScheduleConfig], count: usize) -> Result<(), crate::error::Error> {
+    let format = time::macros::format_description!(
+        "[year]-[month]-[day] [weekday repr:short] [hour]:[minute] [offset_hour sign:mandatory]:[offset_minute]"
+    );
+    let now = OffsetDateTime::now_local().map_err(crate::error::Error::TimeOffset)?;
+
+    for schedule in load(configs)? {
+        println!(
+            "{} ({}, {})",
+            schedule.config.name,
+            schedule.config.cron,
+            schedule.config.timezone.as_deref().unwrap_or("local time")
+        );
+
+        let mut after = now;
+        for _ in 0..count {
+            let Some(next) = schedule.next_after(after) else {
+                println!("  never");
+                break;
+            };
+
+            println!(
+                "  {}",
+                next.format(&format)
+                    .map_err(crate::error::Error::TimeFormatting)?
+            );
+            after = next;
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends the events of `schedules` into the event channel when they are due
+pub struct SchedulerTask {
+    schedules: Vec<Schedule>,
+    cancellation_token: CancellationToken,
+    event_sender: tokio::sync::mpsc::Sender<Event>,
+}
+
+impl SchedulerTask {
+    pub fn new(
+        schedules: Vec<Schedule>,
+        cancellation_token: CancellationToken,
+        event_sender: tokio::sync::mpsc::Sender<Event>,
+    ) -> Self {
+        Self {
+            schedules,
+            cancellation_token,
+            event_sender,
+        }
+    }
+
+    pub async fn run(self) -> Result<(), crate::error::Error> {
+        let mut last_check =
+            OffsetDateTime::now_local().map_err(crate::error::Error::TimeOffset)?;
+
+        loop {
+            let now = OffsetDateTime::now_local().map_err(crate::error::Error::TimeOffset)?;
+
+            // Everything that was due since the last check fires once, even if it was due
+            // multiple times because the system clock jumped
+            for schedule in self.schedules.iter() {
+                let due = schedule.next_after(last_check).is_some_and(|at| at <= now);
+                if !due {
+                    continue;
+                }
+
+                tracing::info!(schedule = schedule.config.name, "Sending scheduled event");
+                if let Err(error) = self.event_sender.send(schedule.event()).await {
+                    tracing::error!(?error, "Failed to send event to channel");
+                }
+            }
+            last_check = now;
+
+            let next = self
+                .schedules
+                .iter()
+                .filter_map(|schedule| schedule.next_after(now))
+                .min();
+            let sleep = next
+                .and_then(|next| std::time::Duration::try_from(next - now).ok())
+                .map_or(MAX_SLEEP, |until_next| until_next.min(MAX_SLEEP));
+            tracing::trace!(?next, ?sleep, "Waiting for next scheduled event");
+
+            let Some(()) = self
+                .cancellation_token
+                .run_until_cancelled(tokio::time::sleep(sleep))
+                .await
+            else {
+                tracing::info!("Ending scheduler");
+                break;
+            };
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::datetime;
+    use time_tz::timezones::db::europe::BERLIN;
+
+    use super::Schedule;
+    use crate::config::ScheduleConfig;
+    use crate::event::EventInner;
+
+    fn config(cron: &str, timezone: Option<&str>) -> ScheduleConfig {
+        ScheduleConfig {
+            name: String::from("test"),
+            cron: cron.to_string(),
+            timezone: timezone.map(ToString::to_string),
+            event: EventInner::TurnOn,
+            priority: Default::default(),
+        }
+    }
+
+    fn schedule(cron: &str, timezone: Option<&str>) -> Schedule {
+        Schedule::new(config(cron, timezone), BERLIN).unwrap()
+    }
+
+    #[test]
+    fn test_timezone() {
+        let schedule = schedule("0 7 * * *", Some("America/New_York"));
+        assert_eq!(
+            schedule.next_after(datetime!(2025-10-19 12:00 UTC)),
+            Some(datetime!(2025-10-20 07:00 -4))
+        );
+    }
+
+    #[test]
+    fn test_local_timezone_follows_daylight_saving_time() {
+        // Clocks in Berlin go back from 03:00 to 02:00 on 2025-10-26
+        let schedule = schedule("0 7 * * *", None);
+        assert_eq!(
+            schedule.next_after(datetime!(2025-10-25 12:00 UTC)),
+            Some(datetime!(2025-10-26 07:00 +1))
+        );
+    }
+
+    #[test]
+    fn test_skips_nonexistent_time() {
+        // Clocks in Berlin jump from 02:00 to 03:00 on 2025-03-30
+        let schedule = schedule("30 2 * * *", Some("Europe/Berlin"));
+        assert_eq!(
+            schedule.next_after(datetime!(2025-03-29 12:00 UTC)),
+            Some(datetime!(2025-03-31 02:30 +2))
+        );
+    }
+
+    #[test]
+    fn test_repeated_time_fires_once() {
+        let schedule = schedule("30 2 * * *", None);
+        assert_eq!(
+            schedule.next_after(datetime!(2025-10-25 12:00 UTC)),
+            Some(datetime!(2025-10-26 02:30 +2))
+        );
+
+        // 02:10 for the second time, after 02:30 passed the first time
+        assert_eq!(
+            schedule.next_after(datetime!(2025-10-26 01:10 UTC)),
+            Some(datetime!(2025-10-27 02:30 +1))
+        );
+    }
+
+    #[test]
+    fn test_day_of_month_or_day_of_week() {
+        // The 13th or any Friday
+        let schedule = schedule("0 12 13 * FRI", Some("UTC"));
+        assert_eq!(
+            schedule.next_after(datetime!(2025-10-19 12:00 UTC)),
+            Some(datetime!(2025-10-24 12:00 UTC))
+        );
+        assert_eq!(
+            schedule.next_after(datetime!(2025-11-10 12:00 UTC)),
+            Some(datetime!(2025-11-13 12:00 UTC))
+        );
+    }
+
+    #[test]
+    fn test_never_matches() {
+        let schedule = schedule("0 0 31 2 *", None);
+        assert_eq!(schedule.next_after(datetime!(2025-10-19 12:00 UTC)), None);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Schedule::new(config("0 7 * *", None), BERLIN).is_err());
+        assert!(Schedule::new(config("0 0 7 * * *", None), BERLIN).is_err());
+        assert!(Schedule::new(config("60 7 * * *", None), BERLIN).is_err());
+        assert!(Schedule::new(config("@daily", Some("Mars/Olympus_Mons")), BERLIN).is_err());
+    }
+}
@@ -0,0 +1,905 @@
+//! Versions of the event wire format
+//!
+//! Version 1 is the format `EventInner` deserializes from, and is assumed if `version` is not
+//! set. Version 2 names fields consistently: durations are always `duration_secs`, colors are
+//! always a `color` object and every event with values is an object. Version 2 events are
+//! translated to version 1 before they are deserialized.
+
+use serde::de::Error as _;
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value;
+use serde_path_to_error::Segment;
+
+use crate::event::Event;
+use crate::event::EventInner;
+use crate::event::Priority;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Version {
+    #[value(name = "1")]
+    V1,
+
+    #[value(name = "2")]
+    V2,
+}
+
+/// An event as received, before it is translated according to its `version`
+#[derive(Debug, serde::Deserialize)]
+pub struct VersionedEvent {
+    #[serde(default = "default_version")]
+    version: u64,
+    event: Value,
+    #[serde(default)]
+    priority: Priority,
+}
+
+fn default_version() -> u64 {
+    1
+}
+
+impl VersionedEvent {
+    /// The `event` translated to version 1
+    fn v1_event(self) -> Result<Value, TranslationError> {
+        match self.version {
+            1 => Ok(self.event),
+            2 => v2_to_v1(self.event).map_err(|error| error.within(["event"])),
+            version => Err(TranslationError {
+                path: vec![String::from("version")],
+                source: serde_json::Error::custom(format!(
+                    "unsupported event version {version}, expected 1 or 2"
+                )),
+            }),
+        }
+    }
+}
+
+/// An event that cannot be translated to version 1
+struct TranslationError {
+    /// Keys from the payload to the value that cannot be translated
+    path: Vec<String>,
+    source: serde_json::Error,
+}
+
+impl TranslationError {
+    /// The error with `keys` prepended to its path
+    fn within<const N: usize>(mut self, keys: [&str; N]) -> Self {
+        self.path.splice(0..0, keys.into_iter().map(String::from));
+        self
+    }
+}
+
+impl TryFrom<VersionedEvent> for Event {
+    type Error = serde_json::Error;
+
+    fn try_from(versioned: VersionedEvent) -> Result<Self, Self::Error> {
+        let priority = versioned.priority;
+        Ok(Event {
+            event: serde_json::from_value(versioned.v1_event().map_err(|error| error.source)?)?,
+            priority,
+        })
+    }
+}
+
+/// JSON pointer to the value in `payload` that could not be deserialized as event
+///
+/// Errors in version 2 events point into the event as it was sent, not as translated to
+/// version 1.
+pub fn error_path(payload: &[u8]) -> Option<String> {
+    let deserializer = &mut serde_json::Deserializer::from_slice(payload);
+    let versioned = match serde_path_to_error::deserialize::<_, VersionedEvent>(deserializer) {
+        Ok(versioned) => versioned,
+        Err(error) => return Some(pointer(&keys(error.path()))),
+    };
+
+    let version = versioned.version;
+    let event = versioned.event.clone();
+    let v1_event = match versioned.v1_event() {
+        Ok(v1_event) => v1_event,
+        Err(error) => return Some(pointer(&error.path)),
+    };
+
+    let error = serde_path_to_error::deserialize::<_, EventInner>(v1_event).err()?;
+    let path = match version {
+        2 => v2_path(&event, &keys(error.path())),
+        _ => keys(error.path()),
+    };
+    Some(format!("/event{}", pointer(&path)))
+}
+
+fn keys(path: &serde_path_to_error::Path) -> Vec<String> {
+    path.iter()
+        .filter_map(|segment| match segment {
+            Segment::Seq { index } => Some(index.to_string()),
+            Segment::Map { key } => Some(key.clone()),
+            Segment::Enum { variant } => Some(variant.clone()),
+            Segment::Unknown => None,
+        })
+        .collect()
+}
+
+fn pointer(keys: &[String]) -> String {
+    keys.iter()
+        .map(|key| format!("/{}", key.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
+
+/// Translate a `path` into the version 1 translation of `event` to a path into `event`
+fn v2_path(event: &Value, path: &[String]) -> Vec<String> {
+    let Some((name, rest)) = path.split_first() else {
+        return Vec::new();
+    };
+    let Some(body) = event.get(name) else {
+        return path.to_vec();
+    };
+
+    let mut v2 = vec![name.clone()];
+    match rest {
+        [] if name == "SetBrightness" && body.get("brightness").is_some() => {
+            v2.push(String::from("brightness"));
+        }
+
+        [steps, index, event, rest @ ..] if steps == "steps" && event == "event" => {
+            v2.extend([steps.clone(), index.clone(), event.clone()]);
+            match element(body, steps, index).and_then(|step| step.get("event")) {
+                Some(step_event) => v2.extend(v2_path(step_event, rest)),
+                None => v2.extend_from_slice(rest),
+            }
+        }
+
+        [thresholds, index, rest @ ..] if thresholds == "thresholds" => {
+            v2.extend([thresholds.clone(), index.clone()]);
+            v2.extend(color_path(element(body, thresholds, index), rest));
+        }
+
+        fields @ [field, rest @ ..] => match V2_RENAMES
+            .iter()
+            .find(|(variant, _, v1_name)| variant == name && v1_name == field)
+        {
+            Some((_, v2_name, _)) => {
+                v2.push(v2_name.to_string());
+                v2.extend_from_slice(rest);
+            }
+            None => v2.extend(color_path(Some(body), fields)),
+        },
+
+        [] => {}
+    }
+    v2
+}
+
+/// The element at `index` of the array `field` of `object`
+fn element<'a>(object: &'a Value, field: &str, index: &str) -> Option<&'a Value> {
+    object.get(field)?.get(index.parse::<usize>().ok()?)
+}
+
+/// Translate a `path` into a flattened color to a path into the `color` object of `object`
+fn color_path(object: Option<&Value>, path: &[String]) -> Vec<String> {
+    let color = object.and_then(|object| object.get("color"));
+    match path.first() {
+        Some(field) if color.is_some_and(|color| color.get(field).is_some()) => {
+            std::iter::once(String::from("color"))
+                .chain(path.iter().cloned())
+                .collect()
+        }
+        _ => path.to_vec(),
+    }
+}
+
+/// Fields with a different name in version 2, as variant, version 2 and version 1 name
+const V2_RENAMES: &[(&str, &str, &str)] = &[
+    ("ShowPreset", "duration_secs", "duration_s"),
+    ("Json", "duration_secs", "sleep_s"),
+];
+
+/// Translate the names of a version 2 event to version 1
+fn v2_to_v1(event: Value) -> Result<Value, TranslationError> {
+    let Value::Object(map) = event else {
+        // Events without values are the same in both versions
+        return Ok(event);
+    };
+    if map.len() != 1 {
+        return Ok(Value::Object(map));
+    }
+
+    let (name, body) = map.into_iter().next().unwrap();
+    let body = match body {
+        Value::Object(mut body) if name == "SetBrightness" => {
+            // The version 1 value has no room for other fields, which would be dropped silently
+            if let Some(field) = body.keys().find(|field| *field != "brightness") {
+                return Err(TranslationError {
+                    path: vec![name, field.clone()],
+                    source: serde_json::Error::unknown_field(field, &["brightness"]),
+                });
+            }
+            body.remove("brightness").unwrap_or(Value::Null)
+        }
+
+        Value::Object(mut body) => {
+            for (_, v2_name, v1_name) in V2_RENAMES.iter().filter(|(variant, ..)| *variant == name)
+            {
+                rename(&mut body, v2_name, v1_name);
+            }
+
+            flatten_color(&mut body);
+            if let Some(Value::Array(thresholds)) = body.get_mut("thresholds") {
+                thresholds
+                    .iter_mut()
+                    .filter_map(Value::as_object_mut)
+                    .for_each(flatten_color);
+            }
+
+            if let Some(Value::Array(steps)) = body.get_mut("steps") {
+                for (index, step) in steps.iter_mut().enumerate() {
+                    let Some(step) = step.as_object_mut() else {
+                        continue;
+                    };
+                    if let Some(event) = step.remove("event") {
+                        let event = v2_to_v1(event).map_err(|error| {
+                            error.within([&name, "steps", &index.to_string(), "event"])
+                        })?;
+                        step.insert(String::from("event"), event);
+                    }
+                }
+            }
+
+            Value::Object(body)
+        }
+
+        other => other,
+    };
+
+    Ok(json!({ name: body }))
+}
+
+fn rename(body: &mut Map<String, Value>, from: &str, to: &str) {
+    if let Some(value) = body.remove(from) {
+        body.insert(to.to_string(), value);
+    }
+}
+
+/// Replace a `color` object by its `r`, `g` and `b` fields
+fn flatten_color(body: &mut Map<String, Value>) {
+    if let Some(Value::Object(color)) = body.remove("color") {
+        body.extend(color);
+    }
+}
+
+/// JSON Schema of the events of `version`
+pub fn event_schema(version: Version) -> Value {
+    let (version_number, required) = match version {
+        Version::V1 => (1, json!(["event"])),
+        Version::V2 => (2, json!(["version", "event"])),
+    };
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": format!("clock event, version {version_number}"),
+        "type": "object",
+        "properties": {
+            "version": { "const": version_number },
+            "event": { "$ref": "#/$defs/event" },
+            "priority": {
+                "description": "Overlays with a higher priority preempt the one shown, High priority notifications are shown during quiet hours",
+                "enum": ["Low", "Normal", "High"],
+                "default": "Normal"
+            }
+        },
+        "required": required,
+        "$defs": {
+            "event": { "oneOf": variants(version) },
+            "color": object(
+                vec![("r", byte()), ("g", byte()), ("b", byte())],
+                vec![],
+            ),
+        }
+    })
+}
+
+fn variants(version: Version) -> Vec<Value> {
+    let v2 = version == Version::V2;
+    let duration = |v1_name| if v2 { "duration_secs" } else { v1_name };
+    let color = |mut fields: Vec<(&'static str, Value)>| {
+        if v2 {
+            fields.push(("color", json!({ "$ref": "#/$defs/color" })));
+        } else {
+            fields.extend([("r", byte()), ("g", byte()), ("b", byte())]);
+        }
+        fields
+    };
+    let overlay_id = || ("id", nullable("string"));
+
+    let set_brightness = if v2 {
+        object(vec![("brightness", percent())], vec![])
+    } else {
+        percent()
+    };
+
+    vec![
+        json!({ "const": "TurnOn" }),
+        json!({ "const": "TurnOff" }),
+        json!({ "const": "ClearQueue", "description": "Drop all queued overlays" }),
+        variant(
+            "SetBrightness",
+            "Set the brightness in percent",
+            set_brightness,
+        ),
+        variant(
+            "Dismiss",
+            "Stop showing the overlay or widget with `id`, or the current one",
+            object(vec![], vec![("id", nullable("string"))]),
+        ),
+        variant(
+            "ShowText",
+            "Show text at `x`, `y` in the time font",
+            object(
+                color(vec![
+                    (duration("duration_secs"), unsigned()),
+                    ("text", string()),
+                    ("x", byte()),
+                    ("y", byte()),
+                ]),
+                vec![overlay_id()],
+            ),
+        ),
+        variant(
+            "ShowPreset",
+            "Show a WLED effect by its name",
+            object(
+                vec![("name", string()), (duration("duration_s"), unsigned())],
+                vec![
+                    overlay_id(),
+                    ("c1", nullable_byte()),
+                    ("c2", nullable_byte()),
+                    ("c3", nullable_byte()),
+                    ("sx", nullable_byte()),
+                    ("ix", nullable_byte()),
+                ],
+            ),
+        ),
+        variant(
+            "Json",
+            "Send `value` to the WLED state API",
+            object(
+                vec![("value", json!({})), (duration("sleep_s"), unsigned())],
+                vec![overlay_id()],
+            ),
+        ),
+        variant(
+            "ShowEffect",
+            "Show an effect rendered by the clock",
+            object(
+                vec![
+                    (
+                        "effect",
+                        json!({ "enum": ["Plasma", "Fire", "MatrixRain", "Starfield", "GameOfLife", "Fireworks"] }),
+                    ),
+                    ("duration_secs", unsigned()),
+                ],
+                vec![overlay_id(), ("seed", unsigned())],
+            ),
+        ),
+        variant(
+            "Sequence",
+            "Send `steps` one after another, `loops` times",
+            object(
+                vec![(
+                    "steps",
+                    json!({
+                        "type": "array",
+                        "items": object(vec![
+                                ("event", json!({ "$ref": "#/$defs/event" })),
+                                ("duration_secs", unsigned()),
+                            ],
+                            vec![],
+                        ),
+                    }),
+                )],
+                vec![overlay_id(), ("loops", unsigned())],
+            ),
+        ),
+        variant(
+            "PlaySequence",
+            "Play a sequence from the configuration",
+            object(vec![("name", string())], vec![]),
+        ),
+        variant(
+            "Template",
+            "Send the event of a template from the configuration",
+            object(
+                vec![("name", string())],
+                vec![("params", json!({ "type": "object" }))],
+            ),
+        ),
+        variant(
+            "ShowProgress",
+            "Show a progress bar",
+            object(
+                color(vec![
+                    ("id", string()),
+                    ("duration_secs", unsigned()),
+                    ("percent", percent()),
+                ]),
+                vec![("label", nullable("string"))],
+            ),
+        ),
+        variant(
+            "ShowGauge",
+            "Show a gauge for a value between `min` and `max`",
+            object(
+                color(vec![
+                    ("id", string()),
+                    ("duration_secs", unsigned()),
+                    ("style", json!({ "enum": ["Horizontal", "Radial"] })),
+                    ("min", number()),
+                    ("max", number()),
+                    ("value", number()),
+                ]),
+                vec![
+                    ("label", nullable("string")),
+                    (
+                        "thresholds",
+                        json!({
+                            "type": "array",
+                            "items": object(color(vec![("from", number())]), vec![]),
+                        }),
+                    ),
+                ],
+            ),
+        ),
+        variant(
+            "ShowChart",
+            "Show a chart of a series of values",
+            object(
+                color(vec![
+                    ("id", string()),
+                    ("duration_secs", unsigned()),
+                    ("style", json!({ "enum": ["Bars", "Line"] })),
+                    ("values", json!({ "type": "array", "items": number() })),
+                ]),
+                vec![
+                    ("min", nullable("number")),
+                    ("max", nullable("number")),
+                    (
+                        "gradient_to",
+                        json!({ "oneOf": [{ "$ref": "#/$defs/color" }, { "type": "null" }] }),
+                    ),
+                ],
+            ),
+        ),
+        variant(
+            "ShowQrCode",
+            "Show text as QR code, Micro QR codes are used if no regular one fits, as on a 32x16 matrix",
+            object(
+                color(vec![
+                    ("id", string()),
+                    ("duration_secs", unsigned()),
+                    ("text", string()),
+                ]),
+                vec![("micro", nullable("boolean"))],
+            ),
+        ),
+    ]
+}
+
+/// An externally tagged enum variant with values
+fn variant(name: &str, description: &str, body: Value) -> Value {
+    json!({
+        "description": description,
+        "type": "object",
+        "properties": { name: body },
+        "required": [name],
+        "additionalProperties": false
+    })
+}
+
+fn object(required: Vec<(&str, Value)>, optional: Vec<(&str, Value)>) -> Value {
+    let names = required.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+    let properties = required
+        .into_iter()
+        .chain(optional)
+        .map(|(name, schema)| (name.to_string(), schema))
+        .collect::<Map<_, _>>();
+
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": names,
+        "additionalProperties": false
+    })
+}
+
+fn string() -> Value {
+    json!({ "type": "string" })
+}
+
+fn number() -> Value {
+    json!({ "type": "number" })
+}
+
+fn unsigned() -> Value {
+    json!({ "type": "integer", "minimum": 0 })
+}
+
+fn byte() -> Value {
+    json!({ "type": "integer", "minimum": 0, "maximum": 255 })
+}
+
+fn percent() -> Value {
+    json!({ "type": "integer", "minimum": 0, "maximum": 100 })
+}
+
+fn nullable(kind: &str) -> Value {
+    json!({ "type": [kind, "null"] })
+}
+
+fn nullable_byte() -> Value {
+    json!({ "type": ["integer", "null"], "minimum": 0, "maximum": 255 })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::event_schema;
+    use super::Version;
+    use crate::event::Event;
+    use crate::event::EventInner;
+
+    /// Fails to compile if a variant is added, which then has to be added to the schema and
+    /// to the list below
+    fn variant_name(event: &EventInner) -> &'static str {
+        match event {
+            EventInner::TurnOn => "TurnOn",
+            EventInner::TurnOff => "TurnOff",
+            EventInner::SetBrightness(_) => "SetBrightness",
+            EventInner::Dismiss { .. } => "Dismiss",
+            EventInner::ClearQueue => "ClearQueue",
+            EventInner::ShowText { .. } => "ShowText",
+            EventInner::ShowPreset { .. } => "ShowPreset",
+            EventInner::Json { .. } => "Json",
+            EventInner::ShowEffect { .. } => "ShowEffect",
+            EventInner::Sequence { .. } => "Sequence",
+            EventInner::PlaySequence { .. } => "PlaySequence",
+            EventInner::Template { .. } => "Template",
+            EventInner::ShowProgress { .. } => "ShowProgress",
+            EventInner::ShowGauge { .. } => "ShowGauge",
+            EventInner::ShowChart { .. } => "ShowChart",
+            EventInner::ShowQrCode { .. } => "ShowQrCode",
+        }
+    }
+
+    const VARIANTS: &[&str] = &[
+        "TurnOn",
+        "TurnOff",
+        "SetBrightness",
+        "Dismiss",
+        "ClearQueue",
+        "ShowText",
+        "ShowPreset",
+        "Json",
+        "ShowEffect",
+        "Sequence",
+        "PlaySequence",
+        "Template",
+        "ShowProgress",
+        "ShowGauge",
+        "ShowChart",
+        "ShowQrCode",
+    ];
+
+    fn schema_variants(version: Version) -> Vec<String> {
+        event_schema(version)["$defs"]["event"]["oneOf"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|variant| match variant.get("const") {
+                Some(name) => name.as_str().unwrap().to_string(),
+                None => variant["required"][0].as_str().unwrap().to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_schema_has_every_variant() {
+        for version in [Version::V1, Version::V2] {
+            let mut names = schema_variants(version);
+            names.sort();
+            let mut expected = VARIANTS.to_vec();
+            expected.sort();
+            assert_eq!(names, expected);
+        }
+    }
+
+    const TESTFILES: &[&str] = &[
+        include_str!("../test/clear_queue.json"),
+        include_str!("../test/dismiss.json"),
+        include_str!("../test/effect.json"),
+        include_str!("../test/fireworks.json"),
+        include_str!("../test/play_sequence.json"),
+        include_str!("../test/sequence.json"),
+        include_str!("../test/set_brightness_20.json"),
+        include_str!("../test/show_chart.json"),
+        include_str!("../test/show_effect.json"),
+        include_str!("../test/show_gauge.json"),
+        include_str!("../test/show_hello.json"),
+        include_str!("../test/show_progress.json"),
+        include_str!("../test/show_qr_code.json"),
+        include_str!("../test/template.json"),
+        include_str!("../test/turn_off.json"),
+        include_str!("../test/turn_on.json"),
+        include_str!("../test/v2_show_text.json"),
+    ];
+
+    #[test]
+    fn test_testfiles_cover_every_variant() {
+        let mut names = TESTFILES
+            .iter()
+            .map(|testfile| {
+                let event: Event = serde_json::from_str(testfile).unwrap();
+                variant_name(&event.event)
+            })
+            .collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+
+        let mut expected = VARIANTS.to_vec();
+        expected.sort();
+        assert_eq!(names, expected);
+    }
+
+    fn assert_valid(validator: &jsonschema::Validator, instance: &Value) {
+        let errors = validator
+            .iter_errors(instance)
+            .map(|error| format!("{}: {error}", error.instance_path))
+            .collect::<Vec<_>>();
+        assert!(
+            errors.is_empty(),
+            "{instance} does not match the schema: {errors:#?}"
+        );
+    }
+
+    /// Checks every field of every variant, as the testfiles cover every variant
+    #[test]
+    fn test_testfiles_match_schema() {
+        let v1 = jsonschema::validator_for(&event_schema(Version::V1)).unwrap();
+        let v2 = jsonschema::validator_for(&event_schema(Version::V2)).unwrap();
+
+        for testfile in TESTFILES {
+            let payload: Value = serde_json::from_str(testfile).unwrap();
+            let validator = match payload.get("version").and_then(Value::as_u64) {
+                Some(2) => &v2,
+                _ => &v1,
+            };
+            assert_valid(validator, &payload);
+
+            // Serialized events have every field, including the optional ones left out in the
+            // testfiles
+            let event: Event = serde_json::from_value(payload).unwrap();
+            assert_valid(&v1, &serde_json::to_value(event).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_schema_rejects_fields_of_other_version() {
+        let v1 = jsonschema::validator_for(&event_schema(Version::V1)).unwrap();
+        let v2 = jsonschema::validator_for(&event_schema(Version::V2)).unwrap();
+        let show_text = serde_json::json!({
+            "event": {
+                "ShowText": {
+                    "duration_secs": 5, "text": "Hi", "r": 1, "g": 2, "b": 3, "x": 0, "y": 8
+                }
+            }
+        });
+
+        assert!(v1.is_valid(&show_text));
+        let mut v2_show_text = show_text.clone();
+        v2_show_text["version"] = serde_json::json!(2);
+        assert!(!v2.is_valid(&v2_show_text));
+    }
+
+    #[test]
+    fn test_v2_is_translated() {
+        let v1: Event = serde_json::from_value(serde_json::json!({
+            "event": {
+                "Sequence": {
+                    "steps": [
+                        {
+                            "event": {
+                                "ShowText": {
+                                    "duration_secs": 5, "text": "Hi", "r": 1, "g": 2, "b": 3, "x": 0, "y": 8
+                                }
+                            },
+                            "duration_secs": 5
+                        },
+                        { "event": { "ShowPreset": { "name": "Fire", "duration_s": 10 } }, "duration_secs": 10 },
+                        { "event": { "Json": { "value": { "on": true }, "sleep_s": 3 } }, "duration_secs": 3 },
+                        { "event": { "SetBrightness": 20 }, "duration_secs": 0 }
+                    ]
+                }
+            },
+            "priority": "High"
+        }))
+        .unwrap();
+
+        let v2: Event = serde_json::from_value(serde_json::json!({
+            "version": 2,
+            "event": {
+                "Sequence": {
+                    "steps": [
+                        {
+                            "event": {
+                                "ShowText": {
+                                    "duration_secs": 5, "text": "Hi", "color": { "r": 1, "g": 2, "b": 3 }, "x": 0, "y": 8
+                                }
+                            },
+                            "duration_secs": 5
+                        },
+                        { "event": { "ShowPreset": { "name": "Fire", "duration_secs": 10 } }, "duration_secs": 10 },
+                        { "event": { "Json": { "value": { "on": true }, "duration_secs": 3 } }, "duration_secs": 3 },
+                        { "event": { "SetBrightness": { "brightness": 20 } }, "duration_secs": 0 }
+                    ]
+                }
+            },
+            "priority": "High"
+        }))
+        .unwrap();
+
+        assert_eq!(
+            serde_json::to_value(v1).unwrap(),
+            serde_json::to_value(v2).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let result = serde_json::from_value::<Event>(serde_json::json!({
+            "version": 3,
+            "event": "TurnOn"
+        }));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_v2_set_brightness_refuses_unknown_fields() {
+        let error = serde_json::from_value::<Event>(serde_json::json!({
+            "version": 2,
+            "event": { "SetBrightness": { "brightnes": 50 } }
+        }))
+        .unwrap_err();
+        assert!(
+            error.to_string().contains("unknown field `brightnes`"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn test_error_path() {
+        let path = |payload: &str| super::error_path(payload.as_bytes());
+
+        assert_eq!(
+            path(r#"{"event": {"ShowText": {"duration_secs": 5, "text": "a", "r": "red"}}}"#),
+            Some(String::from("/event/ShowText/r"))
+        );
+        assert_eq!(
+            path(r#"{"event": {"ShowText": {"duration_secs": 5}}}"#),
+            Some(String::from("/event/ShowText"))
+        );
+        assert_eq!(
+            path(r#"{"event": {"ShowChart": {"values": [1, "two"]}}}"#),
+            Some(String::from("/event/ShowChart/values/1"))
+        );
+        assert_eq!(
+            path(r#"{"event": "TurnOn", "priority": "Urgent"}"#),
+            Some(String::from("/priority"))
+        );
+        // The object that is not closed is the whole payload
+        assert_eq!(path(r#"{"event": "TurnOn""#), Some(String::new()));
+        assert_eq!(
+            path(r#"{"version": 3, "event": "TurnOn"}"#),
+            Some(String::from("/version"))
+        );
+        assert_eq!(path(r#"{"event": "TurnOn"}"#), None);
+    }
+
+    #[test]
+    fn test_nested_error_path() {
+        let path = |payload: serde_json::Value| super::error_path(payload.to_string().as_bytes());
+
+        assert_eq!(
+            path(serde_json::json!({
+                "event": {
+                    "Sequence": {
+                        "steps": [
+                            { "event": "TurnOn", "duration_secs": 1 },
+                            { "event": { "ShowPreset": { "name": "Fire", "duration_s": -1 } }, "duration_secs": 1 }
+                        ]
+                    }
+                }
+            })),
+            Some(String::from(
+                "/event/Sequence/steps/1/event/ShowPreset/duration_s"
+            ))
+        );
+        assert_eq!(
+            path(serde_json::json!({
+                "event": {
+                    "ShowGauge": {
+                        "id": "power", "duration_secs": 5, "style": "Radial", "min": 0, "max": 10, "value": 5,
+                        "r": 1, "g": 2, "b": 3,
+                        "thresholds": [{ "from": 5, "r": 1, "g": 2, "b": 256 }]
+                    }
+                }
+            })),
+            Some(String::from("/event/ShowGauge/thresholds/0/b"))
+        );
+    }
+
+    #[test]
+    fn test_v2_error_path() {
+        let path = |event: serde_json::Value| {
+            let payload = serde_json::json!({ "version": 2, "event": event });
+            super::error_path(payload.to_string().as_bytes())
+        };
+
+        assert_eq!(
+            path(serde_json::json!({ "SetBrightness": { "brightness": "bright" } })),
+            Some(String::from("/event/SetBrightness/brightness"))
+        );
+        assert_eq!(
+            path(serde_json::json!({ "SetBrightness": { "brightnes": 50 } })),
+            Some(String::from("/event/SetBrightness/brightnes"))
+        );
+        assert_eq!(
+            path(serde_json::json!({
+                "Sequence": {
+                    "steps": [{
+                        "event": { "SetBrightness": { "brightness": 50, "fade": true } },
+                        "duration_secs": 1
+                    }]
+                }
+            })),
+            Some(String::from(
+                "/event/Sequence/steps/0/event/SetBrightness/fade"
+            ))
+        );
+        assert_eq!(
+            path(serde_json::json!({ "Json": { "value": {}, "duration_secs": "long" } })),
+            Some(String::from("/event/Json/duration_secs"))
+        );
+        assert_eq!(
+            path(serde_json::json!({
+                "ShowText": {
+                    "duration_secs": 5, "text": "Hi", "color": { "r": 1, "g": "green", "b": 3 }, "x": 0, "y": 8
+                }
+            })),
+            Some(String::from("/event/ShowText/color/g"))
+        );
+        assert_eq!(
+            path(serde_json::json!({
+                "Sequence": {
+                    "steps": [
+                        { "event": "TurnOn", "duration_secs": 1 },
+                        {
+                            "event": { "ShowPreset": { "name": "Fire", "duration_secs": -1 } },
+                            "duration_secs": 1
+                        }
+                    ]
+                }
+            })),
+            Some(String::from(
+                "/event/Sequence/steps/1/event/ShowPreset/duration_secs"
+            ))
+        );
+        assert_eq!(
+            path(serde_json::json!({
+                "ShowGauge": {
+                    "id": "power", "duration_secs": 5, "style": "Radial", "min": 0, "max": 10, "value": 5,
+                    "color": { "r": 1, "g": 2, "b": 3 },
+                    "thresholds": [{ "from": 5, "color": { "r": 1, "g": 2, "b": 256 } }]
+                }
+            })),
+            Some(String::from("/event/ShowGauge/thresholds/0/color/b"))
+        );
+    }
+}
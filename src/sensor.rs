@@ -0,0 +1,129 @@
+use embedded_graphics::pixelcolor::Rgb888;
+
+use crate::config::SensorConfig;
+use crate::error::SensorError;
+
+/// A sensor value, rendered according to its configuration
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading {
+    pub text: String,
+    pub color: Rgb888,
+}
+
+/// Latest values of the sensors from `mqtt.sensors`
+#[derive(Debug)]
+pub struct SensorValues {
+    sensors: Vec<(SensorConfig, Option<f32>)>,
+}
+
+impl SensorValues {
+    pub fn new(configs: Vec<SensorConfig>) -> Self {
+        Self {
+            sensors: configs.into_iter().map(|config| (config, None)).collect(),
+        }
+    }
+
+    /// Update all sensors that are subscribed to `topic`
+    ///
+    /// Returns whether any sensor is subscribed to `topic`.
+    pub fn update(&mut self, topic: &str, payload: &[u8]) -> bool {
+        let mut subscribed = false;
+
+        for (config, value) in self
+            .sensors
+            .iter_mut()
+            .filter(|(config, _)| config.topic == topic)
+        {
+            subscribed = true;
+
+            match parse_value(payload, config.json_pointer.as_deref()) {
+                Ok(new_value) => {
+                    tracing::debug!(?topic, value = new_value, "Updated sensor value");
+                    *value = Some(new_value);
+                }
+                Err(error) => {
+                    tracing::warn!(?topic, ?error, "Failed to parse sensor value, ignoring");
+                }
+            }
+        }
+
+        subscribed
+    }
+
+    /// Rendered reading of the first sensor subscribed to `topic`, if it received a value yet
+    pub fn reading(&self, topic: &str) -> Option<Reading> {
+        self.sensors
+            .iter()
+            .find(|(config, _)| config.topic == topic)
+            .and_then(|(config, value)| value.map(|value| render(config, value)))
+    }
+
+    /// Rendered readings of all sensors that received a value yet
+    pub fn readings(&self) -> Vec<Reading> {
+        self.sensors
+            .iter()
+            .filter_map(|(config, value)| value.map(|value| render(config, value)))
+            .collect()
+    }
+}
+
+fn render(config: &SensorConfig, value: f32) -> Reading {
+    let value_str = format!("{value:.decimals$}", decimals = config.decimals);
+    let text = config
+        .format
+        .replace("{value}", &value_str)
+        .replace("{unit}", &config.unit);
+
+    let color = crate::widget::threshold_color(value, &config.thresholds)
+        .unwrap_or_else(|| config.color.into());
+
+    Reading { text, color }
+}
+
+pub fn parse_value(payload: &[u8], json_pointer: Option<&str>) -> Result<f32, SensorError> {
+    let payload = std::str::from_utf8(payload).map_err(SensorError::Utf8)?;
+
+    let Some(json_pointer) = json_pointer else {
+        return parse_number(payload);
+    };
+
+    let json: serde_json::Value = serde_json::from_str(payload).map_err(SensorError::Json)?;
+    match json.pointer(json_pointer) {
+        None => Err(SensorError::MissingValue(json_pointer.to_string())),
+        Some(serde_json::Value::Number(number)) => number
+            .as_f64()
+            .map(|number| number as f32)
+            .ok_or_else(|| SensorError::NotANumber(number.to_string())),
+        Some(serde_json::Value::String(string)) => parse_number(string),
+        Some(other) => Err(SensorError::NotANumber(other.to_string())),
+    }
+}
+
+fn parse_number(s: &str) -> Result<f32, SensorError> {
+    s.trim()
+        .parse()
+        .map_err(|_| SensorError::NotANumber(s.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_value;
+
+    #[test]
+    fn test_parse_plain_payload() {
+        assert_eq!(parse_value(b" 21.5\n", None).unwrap(), 21.5);
+    }
+
+    #[test]
+    fn test_parse_json_pointer() {
+        let payload = br#"{"temperature": 12.25, "humidity": "65"}"#;
+        assert_eq!(parse_value(payload, Some("/temperature")).unwrap(), 12.25);
+        assert_eq!(parse_value(payload, Some("/humidity")).unwrap(), 65.0);
+        assert!(parse_value(payload, Some("/pressure")).is_err());
+    }
+
+    #[test]
+    fn test_parse_not_a_number() {
+        assert!(parse_value(b"on", None).is_err());
+    }
+}
@@ -0,0 +1,276 @@
+use tokio_util::sync::CancellationToken;
+
+use crate::config::SequenceConfig;
+use crate::event::Event;
+use crate::event::EventInner;
+use crate::event::Priority;
+use crate::event::SequenceStep;
+use crate::template::Templates;
+
+/// Refuse sequences that contain sequences, which could play each other forever
+///
+/// Steps that are templates are checked as the event they expand to.
+pub fn validate(steps: &[SequenceStep], templates: &Templates) -> Result<(), crate::error::Error> {
+    for step in steps.iter() {
+        let nested = match &step.event {
+            EventInner::Template { name, params } => templates
+                .expand(name, params, Priority::default())?
+                .event
+                .is_sequence(),
+            event => event.is_sequence(),
+        };
+        if nested {
+            return Err(crate::error::Error::NestedSequence);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn validate_config(
+    configs: &[SequenceConfig],
+    templates: &Templates,
+) -> Result<(), crate::error::Error> {
+    configs
+        .iter()
+        .try_for_each(|config| validate(&config.steps, templates))
+}
+
+/// Plays sequences by sending their steps into the event channel
+pub struct Sequencer {
+    /// Sequences playing, their token is cancelled once they ended
+    playing: Vec<(Option<String>, CancellationToken)>,
+    cancellation_token: CancellationToken,
+    event_sender: tokio::sync::mpsc::Sender<Event>,
+}
+
+impl Sequencer {
+    pub fn new(
+        cancellation_token: CancellationToken,
+        event_sender: tokio::sync::mpsc::Sender<Event>,
+    ) -> Self {
+        Self {
+            playing: Vec::new(),
+            cancellation_token,
+            event_sender,
+        }
+    }
+
+    /// Start playing `steps` in the background, a sequence with the same `id` is stopped
+    pub fn play(
+        &mut self,
+        id: Option<String>,
+        steps: Vec<SequenceStep>,
+        loops: u32,
+        priority: Priority,
+        templates: &Templates,
+    ) -> Result<(), crate::error::Error> {
+        validate(&steps, templates)?;
+
+        self.playing.retain(|(_, token)| !token.is_cancelled());
+        if let Some(id) = id.as_deref() {
+            self.stop(id);
+        }
+
+        let token = self.cancellation_token.child_token();
+        self.playing.push((id.clone(), token.clone()));
+
+        let steps = steps.into_iter().map(|mut step| {
+            if let (Some(step_id @ None), Some(id)) = (step.event.overlay_id_mut(), id.as_ref()) {
+                *step_id = Some(id.clone());
+            }
+            step
+        });
+
+        tokio::task::spawn(play(
+            steps.collect(),
+            loops,
+            priority,
+            token,
+            self.event_sender.clone(),
+        ));
+
+        Ok(())
+    }
+
+    /// Stop the sequence with `id`, returns whether it was playing
+    pub fn stop(&mut self, id: &str) -> bool {
+        let mut stopped = false;
+        self.playing.retain(|(playing_id, token)| {
+            if playing_id.as_deref() != Some(id) {
+                return true;
+            }
+
+            stopped |= !token.is_cancelled();
+            token.cancel();
+            false
+        });
+        stopped
+    }
+}
+
+async fn play(
+    steps: Vec<SequenceStep>,
+    loops: u32,
+    priority: Priority,
+    cancellation_token: CancellationToken,
+    event_sender: tokio::sync::mpsc::Sender<Event>,
+) {
+    let events = std::iter::repeat_n(steps.iter(), loops as usize).flatten();
+
+    for step in events {
+        let event = Event {
+            event: step.event.clone(),
+            priority,
+        };
+        tracing::debug!(?event, "Sending sequence step");
+        if let Err(error) = event_sender.send(event).await {
+            tracing::error!(?error, "Failed to send event to channel");
+        }
+
+        let wait = tokio::time::sleep(std::time::Duration::from_secs(step.duration_secs.into()));
+        if cancellation_token.run_until_cancelled(wait).await.is_none() {
+            tracing::info!("Sequence stopped");
+            return;
+        }
+    }
+
+    cancellation_token.cancel();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio_util::sync::CancellationToken;
+
+    use super::Sequencer;
+    use crate::config::SequenceConfig;
+    use crate::config::TemplateConfig;
+    use crate::event::EventInner;
+    use crate::event::Priority;
+    use crate::event::SequenceStep;
+    use crate::template::Templates;
+
+    fn text(text: &str, duration_secs: u32) -> SequenceStep {
+        SequenceStep {
+            event: EventInner::ShowText {
+                id: None,
+                duration_secs,
+                text: text.to_string(),
+                r: 0,
+                g: 0,
+                b: 0,
+                x: 0,
+                y: 0,
+            },
+            duration_secs,
+        }
+    }
+
+    /// The texts received until the channel is idle for a minute, with the second they arrived
+    async fn received(
+        receiver: &mut tokio::sync::mpsc::Receiver<crate::event::Event>,
+    ) -> Vec<(String, u64)> {
+        let start = tokio::time::Instant::now();
+        let mut received = Vec::new();
+        while let Ok(Some(event)) =
+            tokio::time::timeout(Duration::from_secs(60), receiver.recv()).await
+        {
+            let EventInner::ShowText { id, text, .. } = event.event else {
+                unreachable!("Only texts are used in tests");
+            };
+            assert_eq!(id.as_deref(), Some("seq"));
+            received.push((text, start.elapsed().as_secs()));
+        }
+        received
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_steps_are_sent_in_order_and_looped() {
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(10);
+        let mut sequencer = Sequencer::new(CancellationToken::new(), sender);
+
+        sequencer
+            .play(
+                Some(String::from("seq")),
+                vec![text("a", 5), text("b", 10)],
+                2,
+                Priority::Normal,
+                &Templates::default(),
+            )
+            .unwrap();
+
+        assert_eq!(
+            received(&mut receiver).await,
+            [
+                (String::from("a"), 0),
+                (String::from("b"), 5),
+                (String::from("a"), 15),
+                (String::from("b"), 20)
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stop() {
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(10);
+        let mut sequencer = Sequencer::new(CancellationToken::new(), sender);
+
+        sequencer
+            .play(
+                Some(String::from("seq")),
+                vec![text("a", 5), text("b", 5)],
+                1,
+                Priority::Normal,
+                &Templates::default(),
+            )
+            .unwrap();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        assert!(sequencer.stop("seq"));
+        assert!(!sequencer.stop("seq"));
+        assert_eq!(received(&mut receiver).await, [(String::from("a"), 0)]);
+    }
+
+    #[test]
+    fn test_nested_sequences_are_refused() {
+        let step = SequenceStep {
+            event: EventInner::PlaySequence {
+                name: String::from("again"),
+            },
+            duration_secs: 1,
+        };
+        assert!(super::validate(&[step], &Templates::default()).is_err());
+    }
+
+    #[test]
+    fn test_templates_expanding_to_sequences_are_refused() {
+        let templates = Templates::new(vec![TemplateConfig {
+            name: String::from("again"),
+            params: Vec::new(),
+            event: serde_json::json!({ "PlaySequence": { "name": "loop" } }),
+            priority: None,
+        }])
+        .unwrap();
+        let sequence = SequenceConfig {
+            name: String::from("loop"),
+            steps: vec![
+                text("a", 5),
+                SequenceStep {
+                    event: EventInner::Template {
+                        name: String::from("again"),
+                        params: serde_json::Map::new(),
+                    },
+                    duration_secs: 1,
+                },
+            ],
+            loops: 1,
+        };
+
+        assert!(std::matches!(
+            super::validate_config(&[sequence], &templates),
+            Err(crate::error::Error::NestedSequence)
+        ));
+    }
+}
@@ -0,0 +1,134 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+use tokio::sync::watch;
+
+use crate::event::EventInner;
+
+/// What the display shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    Clock,
+    Overlay,
+
+    /// WLED shows a preset or JSON state instead of the pixels we send
+    Preset,
+}
+
+impl Mode {
+    /// The mode while the overlay `event` is shown
+    pub fn of_overlay(event: &EventInner) -> Self {
+        match event {
+            EventInner::ShowPreset { .. } | EventInner::Json { .. } => Mode::Preset,
+            _ => Mode::Overlay,
+        }
+    }
+}
+
+/// State of the clock, published retained to `{prefix}/state`
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct ClockState {
+    pub on: bool,
+    pub brightness: u8,
+    pub mode: Mode,
+
+    /// `id` of the overlay shown
+    pub notification_id: Option<String>,
+
+    /// Number of overlays waiting to be shown
+    pub queue_length: usize,
+}
+
+/// Whether the clock is drawn and sent to the display
+///
+/// It is while the display is turned on and not paused for WLED to show a preset or JSON state.
+/// The two are tracked separately, so that an overlay ending while the display is off does not
+/// start the clock.
+#[derive(Debug)]
+pub struct ClockRunning {
+    on: AtomicBool,
+    paused: AtomicBool,
+}
+
+impl ClockRunning {
+    pub fn new(on: bool) -> Self {
+        Self {
+            on: AtomicBool::new(on),
+            paused: AtomicBool::new(false),
+        }
+    }
+
+    pub fn set_on(&self, on: bool) {
+        self.on.store(on, Ordering::Relaxed);
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.on.load(Ordering::Relaxed) && !self.paused.load(Ordering::Relaxed)
+    }
+}
+
+/// The state of the clock, shared by everything that changes it
+#[derive(Debug, Clone)]
+pub struct SharedState {
+    sender: watch::Sender<ClockState>,
+}
+
+impl SharedState {
+    pub fn new(state: ClockState) -> Self {
+        Self {
+            sender: watch::Sender::new(state),
+        }
+    }
+
+    /// Apply `update`, subscribers are only notified if it changed the state
+    pub fn update(&self, update: impl FnOnce(&mut ClockState)) {
+        self.sender.send_if_modified(|state| {
+            let before = state.clone();
+            update(state);
+            *state != before
+        });
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<ClockState> {
+        self.sender.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ClockState;
+    use super::Mode;
+    use super::SharedState;
+
+    #[test]
+    fn test_only_changes_are_notified() {
+        let state = SharedState::new(ClockState {
+            on: true,
+            brightness: 50,
+            mode: Mode::Clock,
+            notification_id: None,
+            queue_length: 0,
+        });
+        let mut receiver = state.subscribe();
+
+        state.update(|state| state.brightness = 50);
+        assert!(!receiver.has_changed().unwrap());
+
+        state.update(|state| state.on = false);
+        assert!(receiver.has_changed().unwrap());
+        insta::assert_json_snapshot!(*receiver.borrow_and_update(), @r#"
+        {
+          "on": false,
+          "brightness": 50,
+          "mode": "clock",
+          "notification_id": null,
+          "queue_length": 0
+        }
+        "#);
+    }
+}
@@ -0,0 +1,222 @@
+use serde_json::Map;
+use serde_json::Value;
+
+use crate::config::TemplateConfig;
+use crate::event::Event;
+use crate::event::EventInner;
+use crate::event::Priority;
+
+/// Events from `templates` in the configuration, with `{param}` placeholders in their strings
+#[derive(Debug, Default)]
+pub struct Templates {
+    templates: Vec<TemplateConfig>,
+}
+
+impl Templates {
+    /// Refuses templates that use parameters they do not declare
+    pub fn new(templates: Vec<TemplateConfig>) -> Result<Self, crate::error::Error> {
+        for template in templates.iter() {
+            let mut undeclared = Vec::new();
+            visit_strings(&template.event, &mut |string| {
+                undeclared.extend(
+                    placeholders(string)
+                        .filter(|param| !template.params.iter().any(|p| p == param))
+                        .map(ToString::to_string),
+                );
+            });
+
+            if let Some(param) = undeclared.first() {
+                return Err(crate::error::Error::UndeclaredTemplateParam {
+                    template: template.name.clone(),
+                    param: param.clone(),
+                });
+            }
+        }
+
+        Ok(Self { templates })
+    }
+
+    /// The event of template `name` with `params` filled in
+    ///
+    /// `priority` is used unless the template sets a priority.
+    pub fn expand(
+        &self,
+        name: &str,
+        params: &Map<String, Value>,
+        priority: Priority,
+    ) -> Result<Event, crate::error::Error> {
+        let template = self
+            .templates
+            .iter()
+            .find(|template| template.name == name)
+            .ok_or_else(|| crate::error::Error::UnknownTemplate(name.to_string()))?;
+
+        if let Some(param) = template
+            .params
+            .iter()
+            .find(|param| !params.contains_key(param.as_str()))
+        {
+            return Err(crate::error::Error::MissingTemplateParam {
+                template: template.name.clone(),
+                param: param.clone(),
+            });
+        }
+
+        if let Some(param) = params.keys().find(|param| !template.params.contains(param)) {
+            return Err(crate::error::Error::UnknownTemplateParam {
+                template: template.name.clone(),
+                param: param.clone(),
+            });
+        }
+
+        let event = serde_json::from_value::<EventInner>(substitute(&template.event, params))
+            .map_err(|source| crate::error::Error::InvalidTemplate {
+                template: template.name.clone(),
+                source,
+            })?;
+
+        if std::matches!(event, EventInner::Template { .. }) {
+            return Err(crate::error::Error::NestedTemplate(template.name.clone()));
+        }
+
+        Ok(Event {
+            event,
+            priority: template.priority.unwrap_or(priority),
+        })
+    }
+}
+
+/// The names of the `{param}` placeholders in `string`
+fn placeholders(string: &str) -> impl Iterator<Item = &str> {
+    string.split('{').skip(1).filter_map(|rest| {
+        let (param, _) = rest.split_once('}')?;
+        (!param.is_empty()).then_some(param)
+    })
+}
+
+fn visit_strings(value: &Value, visit: &mut impl FnMut(&str)) {
+    match value {
+        Value::String(string) => visit(string),
+        Value::Array(values) => values.iter().for_each(|value| visit_strings(value, visit)),
+        Value::Object(map) => map.values().for_each(|value| visit_strings(value, visit)),
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+}
+
+fn substitute(value: &Value, params: &Map<String, Value>) -> Value {
+    match value {
+        Value::String(string) => {
+            let whole = string
+                .strip_prefix('{')
+                .and_then(|rest| rest.strip_suffix('}'))
+                .and_then(|param| params.get(param));
+            if let Some(value) = whole {
+                return value.clone();
+            }
+
+            let replaced = params
+                .iter()
+                .fold(string.clone(), |string, (param, value)| {
+                    let value = match value {
+                        Value::String(value) => value.clone(),
+                        other => other.to_string(),
+                    };
+                    string.replace(&format!("{{{param}}}"), &value)
+                });
+            Value::String(replaced)
+        }
+        Value::Array(values) => Value::Array(
+            values
+                .iter()
+                .map(|value| substitute(value, params))
+                .collect(),
+        ),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| (key.clone(), substitute(value, params)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Templates;
+    use crate::config::TemplateConfig;
+    use crate::event::Priority;
+
+    fn doorbell() -> TemplateConfig {
+        TemplateConfig {
+            name: String::from("doorbell"),
+            params: vec![String::from("name"), String::from("seconds")],
+            event: serde_json::json!({
+                "ShowText": {
+                    "duration_secs": "{seconds}",
+                    "text": "Doorbell: {name}",
+                    "r": 255,
+                    "g": 0,
+                    "b": 0,
+                    "x": 1,
+                    "y": 10
+                }
+            }),
+            priority: Some(Priority::High),
+        }
+    }
+
+    fn params(value: serde_json::Value) -> serde_json::Map<String, serde_json::Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn test_expand() {
+        let templates = Templates::new(vec![doorbell()]).unwrap();
+        let event = templates
+            .expand(
+                "doorbell",
+                &params(serde_json::json!({ "name": "Front", "seconds": 10 })),
+                Priority::Normal,
+            )
+            .unwrap();
+
+        insta::assert_json_snapshot!(event, @r#"
+        {
+          "event": {
+            "ShowText": {
+              "duration_secs": 10,
+              "text": "Doorbell: Front",
+              "r": 255,
+              "g": 0,
+              "b": 0,
+              "x": 1,
+              "y": 10
+            }
+          },
+          "priority": "High"
+        }
+        "#);
+    }
+
+    #[test]
+    fn test_params_are_checked() {
+        let templates = Templates::new(vec![doorbell()]).unwrap();
+        let expand = |value| templates.expand("doorbell", &params(value), Priority::Normal);
+
+        assert!(expand(serde_json::json!({ "name": "Front" })).is_err());
+        assert!(expand(serde_json::json!({ "name": "Front", "seconds": 10, "x": 1 })).is_err());
+        assert!(expand(serde_json::json!({ "name": "Front", "seconds": "ten" })).is_err());
+        assert!(templates
+            .expand("unknown", &serde_json::Map::new(), Priority::Normal)
+            .is_err());
+    }
+
+    #[test]
+    fn test_undeclared_param() {
+        let template = TemplateConfig {
+            params: vec![String::from("name")],
+            ..doorbell()
+        };
+        assert!(Templates::new(vec![template]).is_err());
+    }
+}
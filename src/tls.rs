@@ -0,0 +1,122 @@
+use std::sync::Arc;
+
+use rustls::client::WebPkiServerVerifier;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::CertificateDer;
+use rustls::pki_types::PrivateKeyDer;
+use rustls::pki_types::ServerName;
+use rustls::ClientConfig;
+use rustls::RootCertStore;
+
+use crate::config::MqttTlsConfig;
+use crate::error::TlsError;
+
+/// TLS configuration for the connection to the MQTT broker at `server_name`
+///
+/// rumqttc sends the host it connects to as server name, and verifies the broker certificate
+/// against it.
+pub fn client_config(config: &MqttTlsConfig, server_name: &str) -> Result<ClientConfig, TlsError> {
+    // Fails on every connection attempt otherwise
+    ServerName::try_from(server_name)
+        .map_err(|_| TlsError::InvalidServerName(server_name.to_string()))?;
+
+    let mut roots = RootCertStore::empty();
+    for certificate in read_certificates(&config.ca_file)? {
+        roots
+            .add(certificate)
+            .map_err(TlsError::CertificateAuthority)?;
+    }
+    if roots.is_empty() {
+        return Err(TlsError::NoCertificateAuthority(config.ca_file.clone()));
+    }
+
+    let verifier = WebPkiServerVerifier::builder(Arc::new(roots))
+        .build()
+        .map_err(TlsError::Verifier)?;
+    let builder = ClientConfig::builder().with_webpki_verifier(verifier);
+
+    match (
+        config.client_cert_file.as_ref(),
+        config.client_key_file.as_ref(),
+    ) {
+        (Some(cert_file), Some(key_file)) => {
+            let key =
+                PrivateKeyDer::from_pem_file(key_file).map_err(|source| TlsError::ReadingKey {
+                    path: key_file.clone(),
+                    source,
+                })?;
+            builder
+                .with_client_auth_cert(read_certificates(cert_file)?, key)
+                .map_err(TlsError::ClientAuth)
+        }
+        (None, None) => Ok(builder.with_no_client_auth()),
+        _ => Err(TlsError::IncompleteClientAuth),
+    }
+}
+
+fn read_certificates(path: &camino::Utf8Path) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let reading_certificates = |source| TlsError::ReadingCertificates {
+        path: path.to_path_buf(),
+        source,
+    };
+
+    CertificateDer::pem_file_iter(path)
+        .map_err(reading_certificates)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(reading_certificates)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Host;
+    use crate::config::MqttTlsConfig;
+    use crate::error::TlsError;
+
+    fn path(file: &str) -> camino::Utf8PathBuf {
+        camino::Utf8PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("test/tls")
+            .join(file)
+    }
+
+    fn tls_config() -> MqttTlsConfig {
+        MqttTlsConfig {
+            ca_file: path("ca.pem"),
+            client_cert_file: Some(path("client.pem")),
+            client_key_file: Some(path("client-key.pem")),
+            server_name: Some(Host::Name(String::from("mqtt.test"))),
+        }
+    }
+
+    #[test]
+    fn test_client_config() {
+        assert!(super::client_config(&tls_config(), "mqtt.test").is_ok());
+
+        let config = MqttTlsConfig {
+            client_key_file: None,
+            ..tls_config()
+        };
+        assert!(std::matches!(
+            super::client_config(&config, "mqtt.test"),
+            Err(TlsError::IncompleteClientAuth)
+        ));
+
+        let config = MqttTlsConfig {
+            ca_file: path("missing.pem"),
+            ..tls_config()
+        };
+        assert!(std::matches!(
+            super::client_config(&config, "mqtt.test"),
+            Err(TlsError::ReadingCertificates { .. })
+        ));
+    }
+
+    #[test]
+    fn test_server_name() {
+        assert!(super::client_config(&tls_config(), "192.0.2.1").is_ok());
+        assert!(super::client_config(&tls_config(), "::1").is_ok());
+        assert!(std::matches!(
+            super::client_config(&tls_config(), "[::1]"),
+            Err(TlsError::InvalidServerName(_))
+        ));
+    }
+}
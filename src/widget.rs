@@ -0,0 +1,694 @@
+use std::time::Duration;
+use std::time::Instant;
+
+use embedded_graphics::geometry::Angle;
+use embedded_graphics::geometry::Point;
+use embedded_graphics::geometry::Size;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::Rgb888;
+use embedded_graphics::pixelcolor::RgbColor;
+use embedded_graphics::prelude::DrawTarget;
+use embedded_graphics::primitives::Arc;
+use embedded_graphics::primitives::Circle;
+use embedded_graphics::primitives::Line;
+use embedded_graphics::primitives::Primitive;
+use embedded_graphics::primitives::PrimitiveStyle;
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::Alignment;
+use embedded_graphics::text::Baseline;
+use embedded_graphics::text::Text;
+use embedded_graphics::text::TextStyleBuilder;
+use embedded_graphics::Drawable;
+use embedded_graphics::Pixel;
+
+use crate::event::ChartStyle;
+use crate::event::ColorThreshold;
+use crate::event::GaugeStyle;
+
+/// Font used for labels and values of widgets
+///
+/// The bigger fonts from the configuration leave no room for the widget itself on a 32x16 matrix.
+pub const WIDGET_FONT: embedded_graphics::mono_font::MonoFont<'static> =
+    embedded_graphics::mono_font::ascii::FONT_4X6;
+
+/// Color used for the "empty" part of bars and gauges
+const TRACK_COLOR: Rgb888 = Rgb888::new(20, 20, 20);
+
+/// Light modules around QR codes on each side, scanners need them to find the code
+const QR_CODE_BORDER: u32 = 1;
+
+#[derive(Debug)]
+pub enum Widget {
+    Progress {
+        percent: u8,
+        label: Option<String>,
+        color: Rgb888,
+    },
+
+    Gauge {
+        style: GaugeStyle,
+        min: f32,
+        max: f32,
+        value: f32,
+        label: Option<String>,
+        color: Rgb888,
+        thresholds: Vec<ColorThreshold>,
+    },
+
+    Chart {
+        style: ChartStyle,
+        values: Vec<f32>,
+        min: Option<f32>,
+        max: Option<f32>,
+        color: Rgb888,
+        gradient_to: Option<Rgb888>,
+    },
+
+    QrCode {
+        /// Row-major modules, `true` for dark ones
+        modules: Vec<bool>,
+        width: u32,
+        color: Rgb888,
+    },
+}
+
+impl Widget {
+    /// Encode `text` into the smallest QR code that fits into `max_modules` modules with its
+    /// border
+    ///
+    /// Micro QR codes are smaller, but most phone cameras cannot scan them, so they are only
+    /// used if `micro` is set, or if it is not set and no regular QR code fits.
+    pub fn qr_code(
+        text: &str,
+        color: Rgb888,
+        max_modules: u32,
+        micro: Option<bool>,
+    ) -> Result<Self, crate::error::QrCodeError> {
+        let fits =
+            |version: &qrcode::Version| version.width() as u32 + 2 * QR_CODE_BORDER <= max_modules;
+        let micro = micro.unwrap_or(!fits(&qrcode::Version::Normal(1)));
+
+        let micro_versions = (1..=4).map(qrcode::Version::Micro).filter(|_| micro);
+        let versions = micro_versions
+            .chain((1..=40).map(qrcode::Version::Normal))
+            .filter(fits)
+            .collect::<Vec<_>>();
+
+        if versions.is_empty() {
+            return Err(crate::error::QrCodeError::MatrixTooSmall { max_modules, micro });
+        }
+
+        let code = versions
+            .into_iter()
+            .filter_map(|version| {
+                qrcode::QrCode::with_version(text, version, qrcode::EcLevel::L).ok()
+            })
+            .next()
+            .ok_or(crate::error::QrCodeError::PayloadTooLarge {
+                len: text.len(),
+                max_modules,
+            })?;
+
+        tracing::debug!(version = ?code.version(), "Encoded QR code");
+
+        Ok(Widget::QrCode {
+            modules: code
+                .to_colors()
+                .into_iter()
+                .map(|module| module == qrcode::Color::Dark)
+                .collect(),
+            width: code.width() as u32,
+            color,
+        })
+    }
+}
+
+impl Widget {
+    pub fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb888>,
+    {
+        match self {
+            Widget::Progress {
+                percent,
+                label,
+                color,
+            } => {
+                let percent = (*percent).min(100);
+                let value_text = format!("{percent}%");
+                draw_horizontal(
+                    target,
+                    f32::from(percent) / 100.0,
+                    label.as_deref(),
+                    &value_text,
+                    *color,
+                )
+            }
+
+            Widget::Gauge {
+                style,
+                min,
+                max,
+                value,
+                label,
+                color,
+                thresholds,
+            } => {
+                let color = threshold_color(*value, thresholds).unwrap_or(*color);
+                let fraction = if max > min {
+                    ((value - min) / (max - min)).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                let value_text = format!("{value:.0}");
+
+                match style {
+                    GaugeStyle::Horizontal => {
+                        draw_horizontal(target, fraction, label.as_deref(), &value_text, color)
+                    }
+                    GaugeStyle::Radial => {
+                        draw_radial(target, fraction, label.as_deref(), &value_text, color)
+                    }
+                }
+            }
+
+            Widget::Chart {
+                style,
+                values,
+                min,
+                max,
+                color,
+                gradient_to,
+            } => draw_chart(target, *style, values, *min, *max, *color, *gradient_to),
+
+            Widget::QrCode {
+                modules,
+                width,
+                color,
+            } => draw_qr_code(target, modules, *width, *color),
+        }
+    }
+}
+
+pub fn threshold_color(value: f32, thresholds: &[ColorThreshold]) -> Option<Rgb888> {
+    thresholds
+        .iter()
+        .filter(|threshold| value >= threshold.from)
+        .max_by(|a, b| a.from.total_cmp(&b.from))
+        .map(|threshold| Rgb888::new(threshold.r, threshold.g, threshold.b))
+}
+
+/// Label in the top left, value in the top right, bar in the lower half
+fn draw_horizontal<D>(
+    target: &mut D,
+    fraction: f32,
+    label: Option<&str>,
+    value_text: &str,
+    color: Rgb888,
+) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = Rgb888>,
+{
+    let size = target.bounding_box().size;
+    let text_style = MonoTextStyle::new(&WIDGET_FONT, color);
+
+    if let Some(label) = label {
+        Text::with_baseline(label, Point::zero(), text_style, Baseline::Top).draw(target)?;
+    }
+
+    Text::with_text_style(
+        value_text,
+        Point::new(size.width as i32 - 1, 0),
+        text_style,
+        TextStyleBuilder::new()
+            .alignment(Alignment::Right)
+            .baseline(Baseline::Top)
+            .build(),
+    )
+    .draw(target)?;
+
+    let bar_top = (size.height / 2) as i32;
+    let bar_height = size.height - size.height / 2;
+    let filled_width = (size.width as f32 * fraction).round() as u32;
+
+    Rectangle::new(Point::new(0, bar_top), Size::new(size.width, bar_height))
+        .into_styled(PrimitiveStyle::with_fill(TRACK_COLOR))
+        .draw(target)?;
+
+    Rectangle::new(Point::new(0, bar_top), Size::new(filled_width, bar_height))
+        .into_styled(PrimitiveStyle::with_fill(color))
+        .draw(target)
+}
+
+/// Ring in the left square of the matrix, value and label next to it
+fn draw_radial<D>(
+    target: &mut D,
+    fraction: f32,
+    label: Option<&str>,
+    value_text: &str,
+    color: Rgb888,
+) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = Rgb888>,
+{
+    // Like a speedometer: start in the lower left, sweep clockwise to the lower right
+    const START_DEGREES: f32 = 135.0;
+    const SWEEP_DEGREES: f32 = 270.0;
+
+    let size = target.bounding_box().size;
+    let diameter = size.height;
+    let text_style = MonoTextStyle::new(&WIDGET_FONT, color);
+
+    Arc::new(
+        Point::zero(),
+        diameter,
+        Angle::from_degrees(START_DEGREES),
+        Angle::from_degrees(SWEEP_DEGREES),
+    )
+    .into_styled(PrimitiveStyle::with_stroke(TRACK_COLOR, 2))
+    .draw(target)?;
+
+    if fraction > 0.0 {
+        Arc::new(
+            Point::zero(),
+            diameter,
+            Angle::from_degrees(START_DEGREES),
+            Angle::from_degrees(SWEEP_DEGREES * fraction),
+        )
+        .into_styled(PrimitiveStyle::with_stroke(color, 2))
+        .draw(target)?;
+    }
+
+    let text_x = diameter as i32 + 1;
+    Text::with_baseline(value_text, Point::new(text_x, 1), text_style, Baseline::Top)
+        .draw(target)?;
+
+    if let Some(label) = label {
+        Text::with_baseline(
+            label,
+            Point::new(text_x, (size.height / 2) as i32 + 1),
+            text_style,
+            Baseline::Top,
+        )
+        .draw(target)?;
+    }
+
+    Ok(())
+}
+
+/// One column per value, scaled so that `min` is the bottom and `max` the top row
+fn draw_chart<D>(
+    target: &mut D,
+    style: ChartStyle,
+    values: &[f32],
+    min: Option<f32>,
+    max: Option<f32>,
+    color: Rgb888,
+    gradient_to: Option<Rgb888>,
+) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = Rgb888>,
+{
+    let size = target.bounding_box().size;
+
+    // Only the most recent values fit onto the matrix
+    let values = &values[values.len().saturating_sub(size.width as usize)..];
+    if values.is_empty() {
+        return Ok(());
+    }
+
+    let min = min.unwrap_or_else(|| values.iter().copied().fold(f32::INFINITY, f32::min));
+    let max = max.unwrap_or_else(|| values.iter().copied().fold(f32::NEG_INFINITY, f32::max));
+    let fraction_of = |value: f32| {
+        if max > min {
+            ((value - min) / (max - min)).clamp(0.0, 1.0)
+        } else {
+            1.0
+        }
+    };
+    let color_of = |fraction: f32| match gradient_to {
+        Some(gradient_to) => interpolate(color, gradient_to, fraction),
+        None => color,
+    };
+
+    let column_width = (size.width / values.len() as u32).max(1);
+    let top_row = size.height.saturating_sub(1) as f32;
+
+    match style {
+        ChartStyle::Bars => {
+            for (idx, value) in values.iter().enumerate() {
+                let fraction = fraction_of(*value);
+
+                // Every value gets at least one pixel, so that the lowest one is still visible
+                let height = (fraction * top_row).round() as u32 + 1;
+                Rectangle::new(
+                    Point::new(
+                        idx as i32 * column_width as i32,
+                        (size.height - height) as i32,
+                    ),
+                    Size::new(column_width, height),
+                )
+                .into_styled(PrimitiveStyle::with_fill(color_of(fraction)))
+                .draw(target)?;
+            }
+        }
+
+        ChartStyle::Line => {
+            let points = values
+                .iter()
+                .enumerate()
+                .map(|(idx, value)| {
+                    let fraction = fraction_of(*value);
+                    let point = Point::new(
+                        (idx as u32 * column_width + column_width / 2) as i32,
+                        (top_row - (fraction * top_row).round()) as i32,
+                    );
+                    (point, fraction)
+                })
+                .collect::<Vec<_>>();
+
+            if let [(point, fraction)] = points.as_slice() {
+                Pixel(*point, color_of(*fraction)).draw(target)?;
+            }
+
+            for segment in points.windows(2) {
+                let [(from, _), (to, fraction)] = segment else {
+                    unreachable!()
+                };
+
+                Line::new(*from, *to)
+                    .into_styled(PrimitiveStyle::with_stroke(color_of(*fraction), 1))
+                    .draw(target)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Scaled to the full height and centered, the rest of the matrix is the quiet zone
+fn draw_qr_code<D>(
+    target: &mut D,
+    modules: &[bool],
+    width: u32,
+    color: Rgb888,
+) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = Rgb888>,
+{
+    let size = target.bounding_box().size;
+    let module_size = (size.height / (width + 2 * QR_CODE_BORDER)).max(1);
+    let offset = Point::new(
+        (size.width.saturating_sub(width * module_size) / 2) as i32,
+        (size.height.saturating_sub(width * module_size) / 2) as i32,
+    );
+
+    // Light modules are lit, so the code reads dark-on-light like on paper
+    target.clear(color)?;
+
+    for (idx, _) in modules.iter().enumerate().filter(|(_, dark)| **dark) {
+        let idx = idx as u32;
+        let top_left = offset
+            + Point::new(
+                ((idx % width) * module_size) as i32,
+                ((idx / width) * module_size) as i32,
+            );
+
+        Rectangle::new(top_left, Size::new(module_size, module_size))
+            .into_styled(PrimitiveStyle::with_fill(Rgb888::BLACK))
+            .draw(target)?;
+    }
+
+    Ok(())
+}
+
+fn interpolate(from: Rgb888, to: Rgb888, fraction: f32) -> Rgb888 {
+    let channel =
+        |from: u8, to: u8| (f32::from(from) + (f32::from(to) - f32::from(from)) * fraction) as u8;
+
+    Rgb888::new(
+        channel(from.r(), to.r()),
+        channel(from.g(), to.g()),
+        channel(from.b(), to.b()),
+    )
+}
+
+/// Small icons, drawn into a square of `ICON_SIZE` pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Icon {
+    Sun,
+
+    /// Moon at `phase` of the lunar cycle, `0.0` is new moon and `0.5` is full moon
+    Moon {
+        phase: f64,
+    },
+}
+
+pub const ICON_SIZE: u32 = 9;
+
+impl Icon {
+    pub fn draw<D>(&self, target: &mut D, top_left: Point, color: Rgb888) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb888>,
+    {
+        let center = top_left + Point::new(ICON_SIZE as i32 / 2, ICON_SIZE as i32 / 2);
+
+        match self {
+            Icon::Sun => {
+                Circle::with_center(center, 5)
+                    .into_styled(PrimitiveStyle::with_fill(color))
+                    .draw(target)?;
+
+                let ray = ICON_SIZE as i32 / 2;
+                for direction in [
+                    Point::new(0, 1),
+                    Point::new(0, -1),
+                    Point::new(1, 0),
+                    Point::new(-1, 0),
+                ] {
+                    Pixel(center + direction * ray, color).draw(target)?;
+                }
+
+                for direction in [
+                    Point::new(1, 1),
+                    Point::new(1, -1),
+                    Point::new(-1, 1),
+                    Point::new(-1, -1),
+                ] {
+                    Pixel(center + direction * (ray - 1), color).draw(target)?;
+                }
+
+                Ok(())
+            }
+
+            Icon::Moon { phase } => {
+                Circle::with_center(center, ICON_SIZE)
+                    .into_styled(PrimitiveStyle::with_fill(color))
+                    .draw(target)?;
+
+                // The shadow moves out to the left while waxing and comes in from the right while
+                // waning
+                let shift = if *phase <= 0.5 {
+                    -(ICON_SIZE as f64 * phase * 2.0)
+                } else {
+                    ICON_SIZE as f64 * (1.0 - phase) * 2.0
+                };
+
+                Circle::with_center(center + Point::new(shift.round() as i32, 0), ICON_SIZE)
+                    .into_styled(PrimitiveStyle::with_fill(Rgb888::BLACK))
+                    .draw(target)
+            }
+        }
+    }
+}
+
+/// The widget that is currently shown, if any
+#[derive(Debug)]
+struct ActiveWidget {
+    id: String,
+    widget: Widget,
+    until: Instant,
+}
+
+/// Slot holding the widget that should be rendered instead of the clock
+#[derive(Debug, Default)]
+pub struct WidgetSlot {
+    active: Option<ActiveWidget>,
+}
+
+impl WidgetSlot {
+    /// Show `widget` for `duration`
+    ///
+    /// If a widget with the same `id` is already shown, it is replaced without restarting its
+    /// display duration.
+    pub fn show(&mut self, id: String, widget: Widget, duration: Duration) {
+        let now = Instant::now();
+        match self.active.as_mut() {
+            Some(active) if active.id == id && active.until > now => {
+                tracing::debug!(?id, "Updating widget in place");
+                active.widget = widget;
+            }
+            _ => {
+                self.active = Some(ActiveWidget {
+                    id,
+                    widget,
+                    until: now + duration,
+                });
+            }
+        }
+    }
+
+    /// Stop showing the widget, or only the widget with `id`
+    ///
+    /// Returns whether a widget was dismissed.
+    pub fn dismiss(&mut self, id: Option<&str>) -> bool {
+        if self
+            .active
+            .as_ref()
+            .is_some_and(|active| id.is_none_or(|id| active.id == id))
+        {
+            tracing::debug!(?id, "Dismissing widget");
+            self.active = None;
+            return true;
+        }
+
+        false
+    }
+
+    /// Get the widget to render, dropping it if its display duration has passed
+    pub fn current(&mut self) -> Option<&Widget> {
+        if self
+            .active
+            .as_ref()
+            .is_some_and(|active| active.until <= Instant::now())
+        {
+            tracing::debug!("Widget display duration passed");
+            self.active = None;
+        }
+
+        self.active.as_ref().map(|active| &active.widget)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use embedded_graphics::draw_target::DrawTargetExt;
+    use embedded_graphics::geometry::Point;
+    use embedded_graphics::geometry::Size;
+    use embedded_graphics::mock_display::MockDisplay;
+    use embedded_graphics::pixelcolor::Rgb888;
+    use embedded_graphics::primitives::Rectangle;
+
+    use super::Widget;
+    use super::WidgetSlot;
+
+    fn progress(percent: u8) -> Widget {
+        Widget::Progress {
+            percent,
+            label: None,
+            color: Rgb888::new(0, 100, 0),
+        }
+    }
+
+    #[test]
+    fn test_update_in_place_keeps_deadline() {
+        let mut slot = WidgetSlot::default();
+        slot.show(String::from("a"), progress(10), Duration::from_secs(60));
+        let until = slot.active.as_ref().unwrap().until;
+
+        slot.show(String::from("a"), progress(20), Duration::from_secs(600));
+        let active = slot.active.as_ref().unwrap();
+        assert_eq!(active.until, until);
+        assert!(std::matches!(
+            active.widget,
+            Widget::Progress { percent: 20, .. }
+        ));
+    }
+
+    #[test]
+    fn test_other_id_replaces() {
+        let mut slot = WidgetSlot::default();
+        slot.show(String::from("a"), progress(10), Duration::from_secs(60));
+        let until = slot.active.as_ref().unwrap().until;
+
+        slot.show(String::from("b"), progress(20), Duration::from_secs(600));
+        let active = slot.active.as_ref().unwrap();
+        assert_eq!(active.id, "b");
+        assert!(active.until > until);
+    }
+
+    #[test]
+    fn test_dismiss_by_id() {
+        let mut slot = WidgetSlot::default();
+        slot.show(String::from("a"), progress(10), Duration::from_secs(60));
+
+        assert!(!slot.dismiss(Some("b")));
+        assert!(slot.current().is_some());
+        assert!(slot.dismiss(Some("a")));
+        assert!(slot.current().is_none());
+    }
+
+    #[test]
+    fn test_qr_code_smallest_version() {
+        let widget =
+            Widget::qr_code("HTTP://A.B", Rgb888::new(100, 100, 100), 32, Some(false)).unwrap();
+        assert!(std::matches!(widget, Widget::QrCode { width: 21, .. }));
+
+        let widget =
+            Widget::qr_code("HTTP://A.B", Rgb888::new(100, 100, 100), 32, Some(true)).unwrap();
+        assert!(std::matches!(widget, Widget::QrCode { width: 15, .. }));
+    }
+
+    #[test]
+    fn test_qr_code_on_32x16_matrix() {
+        let widget = Widget::qr_code("0123456789", Rgb888::new(100, 100, 100), 16, None).unwrap();
+        assert!(std::matches!(widget, Widget::QrCode { width: 13, .. }));
+
+        let mut display = MockDisplay::<Rgb888>::new();
+        display.set_allow_overdraw(true);
+        let mut target = display.cropped(&Rectangle::new(Point::zero(), Size::new(32, 16)));
+        widget.draw(&mut target).unwrap();
+
+        // The rows above and below the code stay lit
+        let color = Rgb888::new(100, 100, 100);
+        for x in 0..32 {
+            assert_eq!(display.get_pixel(Point::new(x, 0)), Some(color));
+            assert_eq!(display.get_pixel(Point::new(x, 15)), Some(color));
+        }
+    }
+
+    #[test]
+    fn test_qr_code_matrix_too_small() {
+        let error =
+            Widget::qr_code("0123456789", Rgb888::new(100, 100, 100), 16, Some(false)).unwrap_err();
+        assert!(std::matches!(
+            error,
+            crate::error::QrCodeError::MatrixTooSmall {
+                max_modules: 16,
+                micro: false,
+            }
+        ));
+    }
+
+    #[test]
+    fn test_qr_code_payload_too_large() {
+        let error =
+            Widget::qr_code("HTTP://A.B", Rgb888::new(100, 100, 100), 16, None).unwrap_err();
+        assert!(std::matches!(
+            error,
+            crate::error::QrCodeError::PayloadTooLarge {
+                max_modules: 16,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_expired_widget_is_dropped() {
+        let mut slot = WidgetSlot::default();
+        slot.show(String::from("a"), progress(10), Duration::ZERO);
+        assert!(slot.current().is_none());
+    }
+}
@@ -1,18 +1,75 @@
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
 use ddp_rs::connection::DDPConnection;
 use rgb::RGB8;
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::Host;
+
+/// How often the address of the display is resolved again, to follow changes of its address
+const RESOLVE_INTERVAL: Duration = Duration::from_secs(300);
 
+/// Bounds of the wait between resolving again while writing or resolving fails
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Sends pixels via DDP, reconnecting with the next write after a write failed
+///
+/// The address of the display is resolved by the [`ResolverTask`], so that writing never waits
+/// for a lookup.
 pub struct Writer {
-    connection: DDPConnection,
+    address: tokio::sync::watch::Receiver<SocketAddr>,
+    /// Asks the [`ResolverTask`] to resolve the address again
+    resolve_again: Arc<Notify>,
+    udp_port: u16,
+    connection: Option<DDPConnection>,
 }
 
 impl Writer {
-    pub fn new(connection: DDPConnection) -> Self {
-        Self { connection }
+    pub fn connect(
+        mut address: tokio::sync::watch::Receiver<SocketAddr>,
+        resolve_again: Arc<Notify>,
+        udp_port: u16,
+    ) -> Result<Self, crate::error::Error> {
+        let connection = connect(*address.borrow_and_update(), udp_port)?;
+        Ok(Self {
+            address,
+            resolve_again,
+            udp_port,
+            connection: Some(connection),
+        })
+    }
+}
+
+fn connect(address: SocketAddr, udp_port: u16) -> Result<DDPConnection, crate::error::Error> {
+    // The socket of a previous connection is closed already, so the port can be bound again
+    let socket = std::net::UdpSocket::bind(local_address(address, udp_port))
+        .map_err(crate::error::Error::UDPBind)?;
+
+    let connection = DDPConnection::try_new(
+        address,
+        ddp_rs::protocol::PixelConfig::default(), // Default is RGB, 8 bits ber channel
+        ddp_rs::protocol::ID::Default,
+        socket,
+    )?;
+    Ok(connection)
+}
+
+/// The address to bind to for sending to `address`, which has to be of the same family
+fn local_address(address: SocketAddr, udp_port: u16) -> SocketAddr {
+    match address {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, udp_port)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, udp_port)),
     }
 }
 
 impl smart_leds_trait::SmartLedsWrite for Writer {
-    type Error = ddp_rs::error::DDPError;
+    type Error = crate::error::Error;
     type Color = RGB8;
 
     fn write<T, I>(&mut self, iterator: T) -> Result<(), Self::Error>
@@ -20,14 +77,151 @@ impl smart_leds_trait::SmartLedsWrite for Writer {
         T: IntoIterator<Item = I>,
         I: Into<Self::Color>,
     {
-        self.connection
-            .write(
-                &iterator
-                    .into_iter()
-                    .map(|c| c.into())
-                    .flat_map(|rgb| [rgb.r, rgb.g, rgb.b].into_iter())
-                    .collect::<Vec<u8>>(),
-            )
-            .map(drop)
+        if self.address.has_changed().unwrap_or(false) {
+            self.connection = None;
+        }
+
+        let connection = match self.connection.as_mut() {
+            Some(connection) => connection,
+            None => {
+                let address = *self.address.borrow_and_update();
+                tracing::info!(?address, "Reconnecting to display");
+                self.connection.insert(connect(address, self.udp_port)?)
+            }
+        };
+
+        let written = connection.write(
+            &iterator
+                .into_iter()
+                .map(|c| c.into())
+                .flat_map(|rgb| [rgb.r, rgb.g, rgb.b].into_iter())
+                .collect::<Vec<u8>>(),
+        );
+
+        if let Err(error) = written {
+            self.connection = None;
+            self.resolve_again.notify_one();
+            return Err(error.into());
+        }
+        Ok(())
+    }
+}
+
+/// Resolve the address of the display at `host`
+pub async fn resolve(host: &Host, port: u16) -> Result<SocketAddr, crate::error::Error> {
+    let host = host.unbracketed();
+    tokio::net::lookup_host((host.clone(), port))
+        .await
+        .map_err(|source| crate::error::Error::ResolvingDisplay {
+            host: host.clone(),
+            source,
+        })?
+        .next()
+        .ok_or(crate::error::Error::DisplayNotFound(host))
+}
+
+/// Resolves the address of the display periodically, and when the [`Writer`] failed to write
+///
+/// While writing or resolving keeps failing, the address is resolved again after a backoff that
+/// doubles each time, so that a display that is gone is not looked up for every frame.
+pub struct ResolverTask {
+    host: Host,
+    port: u16,
+    address: tokio::sync::watch::Sender<SocketAddr>,
+    resolve_again: Arc<Notify>,
+    cancellation_token: CancellationToken,
+}
+
+impl ResolverTask {
+    pub fn new(
+        host: Host,
+        port: u16,
+        address: tokio::sync::watch::Sender<SocketAddr>,
+        resolve_again: Arc<Notify>,
+        cancellation_token: CancellationToken,
+    ) -> Self {
+        Self {
+            host,
+            port,
+            address,
+            resolve_again,
+            cancellation_token,
+        }
+    }
+
+    pub async fn run(self) -> Result<(), crate::error::Error> {
+        let mut backoff = MIN_BACKOFF;
+        let mut backoff_until = tokio::time::Instant::now();
+        let mut lookup_failed = false;
+
+        loop {
+            let failed = tokio::select! {
+                _cancelled = self.cancellation_token.cancelled() => {
+                    tracing::info!("Ending display resolver");
+                    break;
+                }
+
+                _ = tokio::time::sleep(RESOLVE_INTERVAL) => false,
+
+                _ = async {
+                    tokio::time::sleep_until(backoff_until).await;
+                    if !lookup_failed {
+                        self.resolve_again.notified().await;
+                    }
+                } => true,
+            };
+
+            match resolve(&self.host, self.port).await {
+                Ok(address) => {
+                    lookup_failed = false;
+                    self.address.send_if_modified(|current| {
+                        let changed = *current != address;
+                        if changed {
+                            tracing::info!(previous = ?current, ?address, "Address of display changed");
+                            *current = address;
+                        }
+                        changed
+                    });
+                }
+                Err(error) => {
+                    lookup_failed = true;
+                    tracing::warn!(?error, retry_in = ?backoff, "Failed to resolve display");
+                }
+            }
+
+            if failed || lookup_failed {
+                backoff_until = tokio::time::Instant::now() + backoff;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            } else {
+                backoff = MIN_BACKOFF;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use crate::config::Host;
+
+    #[tokio::test]
+    async fn test_resolve_ip_address() {
+        let host = Host::try_from(String::from("[::1]")).unwrap();
+        let address = super::resolve(&host, 4048).await.unwrap();
+        assert_eq!(address, "[::1]:4048".parse::<SocketAddr>().unwrap());
+        assert_eq!(
+            super::local_address(address, 4049),
+            "[::]:4049".parse::<SocketAddr>().unwrap()
+        );
+
+        let host = Host::try_from(String::from("192.0.2.1")).unwrap();
+        let address = super::resolve(&host, 4048).await.unwrap();
+        assert_eq!(
+            super::local_address(address, 4049),
+            "0.0.0.0:4049".parse::<SocketAddr>().unwrap()
+        );
     }
 }
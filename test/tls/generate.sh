@@ -0,0 +1,29 @@
+#!/usr/bin/env bash
+#
+# Generates the certificates used by the tests and by `mosquitto-tls.conf`
+#
+# The broker certificate is valid for `mqtt.test`, `localhost` and `127.0.0.1`.
+
+set -euo pipefail
+
+cd "$(dirname "$0")"
+
+DAYS=36500
+
+openssl genpkey -algorithm EC -pkeyopt ec_paramgen_curve:P-256 -out ca-key.pem
+openssl req -x509 -new -key ca-key.pem -sha256 -days "$DAYS" \
+    -subj "/CN=clock test CA" -out ca.pem
+
+for name in server client; do
+    openssl genpkey -algorithm EC -pkeyopt ec_paramgen_curve:P-256 -out "$name-key.pem"
+    openssl req -new -key "$name-key.pem" -subj "/CN=clock test $name" -out "$name.csr"
+done
+
+openssl x509 -req -in server.csr -CA ca.pem -CAkey ca-key.pem -CAcreateserial -sha256 \
+    -days "$DAYS" -out server.pem \
+    -extfile <(printf "subjectAltName=DNS:mqtt.test,DNS:localhost,IP:127.0.0.1\nextendedKeyUsage=serverAuth\n")
+openssl x509 -req -in client.csr -CA ca.pem -CAkey ca-key.pem -CAcreateserial -sha256 \
+    -days "$DAYS" -out client.pem \
+    -extfile <(printf "extendedKeyUsage=clientAuth\n")
+
+rm server.csr client.csr ca.srl